@@ -0,0 +1,539 @@
+use ebur128::{EbuR128, Mode};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::tag::Tag as LoftyTag;
+use log::{info, warn};
+use mpd_client::commands;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use tokio::sync::{Mutex, Notify};
+
+use crate::audio::decode_file;
+use crate::catalog::{backfill_catalog, save_catalog};
+use crate::lyrics::extract_lyrics;
+use crate::models::{Loudness, Track};
+use crate::mpd_manager::{apply_cue_points_to_queue, track_id_from_filename};
+use crate::state::AppState;
+
+/// ReplayGain 2.0 reference loudness
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+/// Seconds between checks whether a track that was on air can be tagged yet
+const TAG_RETRY_SECS: u64 = 30;
+
+/// Uploads waiting for background analysis, in arrival order
+#[derive(Default)]
+pub struct AnalysisQueue {
+    pending: Mutex<VecDeque<String>>,
+    notify: Notify,
+}
+
+impl AnalysisQueue {
+    pub async fn push(&self, filename: &str) {
+        let mut pending = self.pending.lock().await;
+        if !pending.iter().any(|f| f == filename) {
+            pending.push_back(filename.to_string());
+        }
+        self.notify.notify_one();
+    }
+    
+    async fn pop(&self) -> String {
+        loop {
+            if let Some(filename) = self.pending.lock().await.pop_front() {
+                return filename;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Length of the windows silence is detected in and waveforms are built from
+const PEAK_WINDOW_SECS: f64 = 0.01;
+/// Number of points in a track's waveform
+const WAVEFORM_POINTS: usize = 1000;
+const DEFAULT_WAVEFORM_DIR: &str = "data/waveforms";
+const DEFAULT_SILENCE_THRESHOLD_DB: f64 = -50.0;
+const DEFAULT_SILENCE_MIN_SECS: f64 = 0.5;
+
+/// Version of the analysis results; tracks whose stored `analysis_version` is lower
+/// are re-analyzed at startup
+pub const ANALYSIS_VERSION: u32 = 4;
+/// Bumped whenever the waveform computation changes, so stored waveforms are rebuilt
+pub const WAVEFORM_VERSION: u32 = 1;
+
+/// Silence detection settings
+/// Environment variables: SILENCE_THRESHOLD_DB (dBFS peak), SILENCE_MIN_SECS
+#[derive(Debug, Clone, Copy)]
+pub struct SilenceConfig {
+    pub threshold_db: f64,
+    /// Shorter leading or trailing silence is left alone
+    pub min_secs: f64,
+}
+
+impl SilenceConfig {
+    pub fn from_env() -> Self {
+        let env_f64 = |name: &str, default: f64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            threshold_db: env_f64("SILENCE_THRESHOLD_DB", DEFAULT_SILENCE_THRESHOLD_DB),
+            min_secs: env_f64("SILENCE_MIN_SECS", DEFAULT_SILENCE_MIN_SECS),
+        }
+    }
+}
+
+/// Everything measured in one decoding pass over an upload
+#[derive(Debug, Clone)]
+pub struct AnalysisResult {
+    pub loudness: Loudness,
+    pub cue_in: Option<f64>,
+    pub cue_out: Option<f64>,
+    /// Peak level per point, 0-255, evenly spread over the track
+    pub waveform: Vec<u8>,
+}
+
+/// Peak level of consecutive short windows, the basis for silence detection and waveforms
+struct WindowPeaks {
+    window_frames: usize,
+    current_peak: f32,
+    current_len: usize,
+    peaks: Vec<f32>,
+    frames: u64,
+}
+
+impl WindowPeaks {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            window_frames: ((sample_rate as f64 * PEAK_WINDOW_SECS) as usize).max(1),
+            current_peak: 0.0,
+            current_len: 0,
+            peaks: Vec::new(),
+            frames: 0,
+        }
+    }
+    
+    fn add_frame(&mut self, frame: &[f32]) {
+        let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        self.current_peak = self.current_peak.max(peak);
+        self.current_len += 1;
+        self.frames += 1;
+        
+        if self.current_len >= self.window_frames {
+            self.peaks.push(self.current_peak);
+            self.current_peak = 0.0;
+            self.current_len = 0;
+        }
+    }
+    
+    fn finish(mut self) -> Vec<f32> {
+        if self.current_len > 0 {
+            self.peaks.push(self.current_peak);
+        }
+        self.peaks
+    }
+}
+
+/// Cue points in seconds from the first and last window above the threshold,
+/// only where the silence is long enough to matter
+fn cue_points(peaks: &[f32], duration: f64, silence: SilenceConfig) -> (Option<f64>, Option<f64>) {
+    let threshold = 10f64.powf(silence.threshold_db / 20.0) as f32;
+    let (Some(first), Some(last)) = (
+        peaks.iter().position(|p| *p > threshold),
+        peaks.iter().rposition(|p| *p > threshold),
+    ) else {
+        return (None, None);
+    };
+    
+    let sound_start = first as f64 * PEAK_WINDOW_SECS;
+    let sound_end = ((last + 1) as f64 * PEAK_WINDOW_SECS).min(duration);
+    (
+        (sound_start >= silence.min_secs).then_some(sound_start),
+        (duration - sound_end >= silence.min_secs).then_some(sound_end),
+    )
+}
+
+/// Downsample window peaks to exactly `points` points scaled to 0-255
+/// Short inputs are stretched, so each point repeats the window it falls in
+fn waveform(peaks: &[f32], points: usize) -> Vec<u8> {
+    if peaks.is_empty() {
+        return vec![0; points];
+    }
+    (0..points)
+        .map(|i| {
+            let start = i * peaks.len() / points;
+            let end = ((i + 1) * peaks.len() / points).max(start + 1);
+            let peak = peaks[start..end].iter().fold(0.0f32, |a, b| a.max(*b));
+            (peak.min(1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Decode a file once and measure EBU R128 loudness, true peak, leading/trailing
+/// silence and the waveform (blocking)
+pub fn analyze_file(path: &Path, silence: SilenceConfig) -> Result<AnalysisResult, String> {
+    let mut meter: Option<EbuR128> = None;
+    let mut window_peaks: Option<WindowPeaks> = None;
+    let mut error = None;
+    
+    let spec = decode_file(path, |samples, spec| {
+        let meter = match &mut meter {
+            Some(meter) => meter,
+            None => match EbuR128::new(spec.channels as u32, spec.sample_rate, Mode::I | Mode::TRUE_PEAK) {
+                Ok(m) => meter.insert(m),
+                Err(e) => {
+                    error = Some(format!("Failed to create loudness meter: {}", e));
+                    return false;
+                }
+            },
+        };
+        if let Err(e) = meter.add_frames_f32(samples) {
+            error = Some(format!("Failed to measure loudness: {}", e));
+            return false;
+        }
+        
+        let window_peaks = window_peaks.get_or_insert_with(|| WindowPeaks::new(spec.sample_rate));
+        for frame in samples.chunks(spec.channels) {
+            window_peaks.add_frame(frame);
+        }
+        true
+    })?;
+    
+    if let Some(e) = error {
+        return Err(e);
+    }
+    let (meter, window_peaks) = meter.zip(window_peaks).ok_or_else(|| "File contains no decodable audio".to_string())?;
+    
+    let integrated_lufs = meter.loudness_global().map_err(|e| format!("Failed to measure loudness: {}", e))?;
+    if !integrated_lufs.is_finite() {
+        return Err("Track is silent".to_string());
+    }
+    
+    let mut track_peak = 0.0f64;
+    for channel in 0..meter.channels() {
+        let peak = meter.true_peak(channel).map_err(|e| format!("Failed to measure true peak: {}", e))?;
+        track_peak = track_peak.max(peak);
+    }
+    
+    let duration = window_peaks.frames as f64 / spec.sample_rate as f64;
+    let peaks = window_peaks.finish();
+    let (cue_in, cue_out) = cue_points(&peaks, duration, silence);
+    
+    Ok(AnalysisResult {
+        loudness: Loudness {
+            integrated_lufs,
+            true_peak_dbtp: 20.0 * track_peak.log10(),
+            track_gain_db: REPLAYGAIN_REFERENCE_LUFS - integrated_lufs,
+            track_peak,
+        },
+        cue_in,
+        cue_out,
+        waveform: waveform(&peaks, WAVEFORM_POINTS),
+    })
+}
+
+/// Where waveforms are stored, one `{track_id}.bin` file of peak bytes per track
+/// Environment variable: WAVEFORM_DIR
+fn waveform_dir() -> PathBuf {
+    PathBuf::from(std::env::var("WAVEFORM_DIR").unwrap_or_else(|_| DEFAULT_WAVEFORM_DIR.to_string()))
+}
+
+fn waveform_path(track_id: &str) -> PathBuf {
+    waveform_dir().join(format!("{}.bin", sanitize_filename::sanitize(track_id)))
+}
+
+pub async fn load_waveform(track_id: &str) -> std::io::Result<Vec<u8>> {
+    tokio::fs::read(waveform_path(track_id)).await
+}
+
+async fn save_waveform(track_id: &str, waveform: &[u8]) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(waveform_dir()).await?;
+    tokio::fs::write(waveform_path(track_id), waveform).await
+}
+
+/// Whether the stored waveform exists and was built by the current version
+async fn has_current_waveform(track: &Track) -> bool {
+    track.waveform_version >= WAVEFORM_VERSION && tokio::fs::try_exists(waveform_path(&track.id)).await.unwrap_or(false)
+}
+
+pub async fn remove_waveform(track_id: &str) {
+    let _ = tokio::fs::remove_file(waveform_path(track_id)).await;
+}
+
+/// Store the measurement as REPLAYGAIN_TRACK_GAIN/PEAK in the file's own tag format (blocking)
+///
+/// Nothing is written when the tags already hold these values. Otherwise a tagged copy
+/// replaces the file, so readers of the old file (MPD, streams) never see it half written;
+/// the copy keeps the original modification time. Returns whether the file changed.
+pub fn write_replaygain_tags(path: &Path, loudness: &Loudness) -> Result<bool, String> {
+    let mut tagged = lofty::read_from_path(path).map_err(|e| format!("Failed to read tags: {}", e))?;
+    let gain = format!("{:.2} dB", loudness.track_gain_db);
+    let peak = format!("{:.6}", loudness.track_peak);
+    
+    if tagged.primary_tag().is_some_and(|tag| {
+        tag.get_string(&ItemKey::ReplayGainTrackGain) == Some(gain.as_str())
+            && tag.get_string(&ItemKey::ReplayGainTrackPeak) == Some(peak.as_str())
+    }) {
+        return Ok(false);
+    }
+    
+    if tagged.primary_tag().is_none() {
+        let tag_type = tagged.primary_tag_type();
+        tagged.insert_tag(LoftyTag::new(tag_type));
+    }
+    let tag = tagged.primary_tag_mut().ok_or_else(|| "File doesn't support tags".to_string())?;
+    tag.insert_text(ItemKey::ReplayGainTrackGain, gain);
+    tag.insert_text(ItemKey::ReplayGainTrackPeak, peak);
+    
+    let name = path.file_name().and_then(|n| n.to_str()).ok_or_else(|| "Invalid file name".to_string())?;
+    // Hidden, so the storage reconciler never indexes it
+    let tmp_path = path.with_file_name(format!(".{}.tagging", name));
+    let result = (|| {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified())
+            .map_err(|e| format!("Failed to read file times: {}", e))?;
+        std::fs::copy(path, &tmp_path).map_err(|e| format!("Failed to copy file: {}", e))?;
+        tag.save_to_path(&tmp_path, WriteOptions::default())
+            .map_err(|e| format!("Failed to write tags: {}", e))?;
+        std::fs::File::options().write(true).open(&tmp_path)
+            .and_then(|file| file.set_modified(modified))
+            .map_err(|e| format!("Failed to keep file times: {}", e))?;
+        std::fs::rename(&tmp_path, path).map_err(|e| format!("Failed to replace file: {}", e))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result.map(|_| true)
+}
+
+/// Whether the file is playing or queued to play next; MPD may have it open, so its
+/// tags are left alone until then
+async fn on_air_soon(state: &AppState, filename: &str) -> bool {
+    let client = state.mpd_client.lock().await;
+    let Ok(status) = client.command(commands::Status).await else {
+        return true;
+    };
+    let ids: Vec<_> = [status.current_song, status.next_song].into_iter().flatten().map(|(_, id)| id).collect();
+    if ids.is_empty() {
+        return false;
+    }
+    match client.command(commands::Queue).await {
+        Ok(queue) => queue.iter().any(|s| ids.contains(&s.id) && s.song.url == filename),
+        Err(_) => true,
+    }
+}
+
+async fn tag_upload(state: &AppState, filename: &str, loudness: &Loudness) {
+    let path = state.storage.path(filename);
+    let loudness = loudness.clone();
+    match tokio::task::spawn_blocking(move || write_replaygain_tags(&path, &loudness)).await {
+        Ok(Ok(true)) => {
+            // Tagging changed the file's size and hash
+            if let Err(e) = state.storage.record_write(filename, None).await {
+                warn!("Failed to reindex {} after tagging: {}", filename, e);
+            }
+        }
+        Ok(Ok(false)) => {}
+        Ok(Err(e)) => warn!("Failed to tag {}: {}", filename, e),
+        Err(e) => warn!("Tagging task for {} failed: {}", filename, e),
+    }
+}
+
+/// Write ReplayGain tags now, or in the background once the track is off air
+async fn tag_when_idle(state: &AppState, filename: &str, loudness: &Loudness) {
+    if !on_air_soon(state, filename).await {
+        tag_upload(state, filename, loudness).await;
+        return;
+    }
+    
+    let state = state.clone();
+    let filename = filename.to_string();
+    let loudness = loudness.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(TAG_RETRY_SECS)).await;
+            // The upload may have been evicted in the meantime
+            if state.storage.get(&filename).await.is_none() {
+                return;
+            }
+            if !on_air_soon(&state, &filename).await {
+                tag_upload(&state, &filename, &loudness).await;
+                return;
+            }
+        }
+    });
+}
+
+/// Measure a catalogued upload and store the results
+async fn analyze_upload(state: &AppState, filename: &str) -> Result<(), String> {
+    let track_id = track_id_from_filename(filename);
+    // Lyrics sent as `.lrc` or set through the API take precedence over embedded ones
+    let has_lyrics = state.tracks_metadata.read().await.get(&track_id).is_some_and(|t| t.lyrics.is_some());
+    if !has_lyrics {
+        extract_lyrics(state, &track_id, filename).await;
+    }
+    
+    let path = state.storage.path(filename);
+    let silence = SilenceConfig::from_env();
+    let result = tokio::task::spawn_blocking(move || analyze_file(&path, silence))
+        .await
+        .map_err(|e| format!("Analysis task failed: {}", e))??;
+    
+    info!(
+        "Analyzed {}: {:.1} LUFS, {:.1} dBTP, gain {:+.2} dB, cue in {:?}, cue out {:?}",
+        filename, result.loudness.integrated_lufs, result.loudness.true_peak_dbtp,
+        result.loudness.track_gain_db, result.cue_in, result.cue_out
+    );
+    
+    let waveform_saved = match save_waveform(&track_id, &result.waveform).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to save waveform of {}: {}", filename, e);
+            false
+        }
+    };
+    let stored_hash = state.storage.get(filename).await.map(|f| f.content_hash);
+    if let Some(track) = state.tracks_metadata.write().await.get_mut(&track_id) {
+        // Keep the hash from before tagging so re-uploads of the original still match
+        if track.upload_hash.is_none() {
+            track.upload_hash = stored_hash;
+        }
+        track.loudness = Some(result.loudness.clone());
+        track.cue_in = result.cue_in;
+        track.cue_out = result.cue_out;
+        track.analysis_version = ANALYSIS_VERSION;
+        if waveform_saved {
+            track.waveform_version = WAVEFORM_VERSION;
+        }
+    }
+    save_catalog(state).await;
+    tag_when_idle(state, filename, &result.loudness).await;
+    
+    // Entries queued before the analysis finished still need their cue points
+    if result.cue_in.is_some() || result.cue_out.is_some() {
+        apply_cue_points_to_queue(state, filename).await;
+    }
+    Ok(())
+}
+
+/// Analyze uploads in the background, one at a time
+/// Catalog tracks last analyzed by an older version or missing a current waveform are
+/// queued at startup (re-analysis only rewrites tags whose values changed), and analyzed
+/// tracks whose tags were never written (e.g. they were on air at shutdown) are tagged
+pub async fn start_analysis_worker(state: AppState) {
+    tokio::spawn(async move {
+        backfill_catalog(&state).await;
+        
+        let mut unanalyzed = Vec::new();
+        for track in state.tracks_metadata.read().await.values() {
+            if track.analysis_version < ANALYSIS_VERSION || !has_current_waveform(track).await {
+                unanalyzed.push(track.filename.clone());
+            }
+        }
+        if !unanalyzed.is_empty() {
+            info!("Queueing {} tracks for analysis", unanalyzed.len());
+        }
+        for filename in unanalyzed {
+            state.analysis_queue.push(&filename).await;
+        }
+        
+        let analyzed: Vec<(String, Loudness)> = state.tracks_metadata.read().await
+            .values()
+            .filter(|t| t.analysis_version >= ANALYSIS_VERSION)
+            .filter_map(|t| Some((t.filename.clone(), t.loudness.clone()?)))
+            .collect();
+        for (filename, loudness) in analyzed {
+            if state.storage.get(&filename).await.is_some() {
+                tag_when_idle(&state, &filename, &loudness).await;
+            }
+        }
+        
+        loop {
+            let filename = state.analysis_queue.pop().await;
+            // The upload may have been evicted while it was waiting
+            if state.storage.get(&filename).await.is_none() {
+                continue;
+            }
+            if let Err(e) = analyze_upload(&state, &filename).await {
+                warn!("Failed to analyze {}: {}", filename, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn waveform_has_exactly_the_requested_points() {
+        for len in [1, 7, 999, 1000, 1001, 1500, 123_457] {
+            assert_eq!(waveform(&vec![0.5; len], WAVEFORM_POINTS).len(), WAVEFORM_POINTS, "{} peaks", len);
+        }
+        assert_eq!(waveform(&[], WAVEFORM_POINTS), vec![0; WAVEFORM_POINTS]);
+    }
+    
+    #[test]
+    fn waveform_stretches_short_inputs() {
+        assert_eq!(waveform(&[0.0, 1.0], 4), vec![0, 0, 255, 255]);
+    }
+    
+    #[test]
+    fn waveform_takes_the_loudest_window_of_each_bucket() {
+        let peaks = [0.1, 1.0, 0.2, 0.0, 0.0, 0.5];
+        assert_eq!(waveform(&peaks, 3), vec![255, 51, 128]);
+        // The last bucket covers the tail instead of being left short
+        assert_eq!(waveform(&[0.0, 0.0, 0.0, 0.0, 1.0], 2), vec![0, 255]);
+    }
+    
+    const SILENCE: SilenceConfig = SilenceConfig { threshold_db: -50.0, min_secs: 0.5 };
+    
+    /// Window peaks for `lead` seconds of silence, `sound` seconds of audio and `tail` seconds of silence
+    fn track(lead: f64, sound: f64, tail: f64) -> (Vec<f32>, f64) {
+        let windows = |secs: f64| (secs / PEAK_WINDOW_SECS).round() as usize;
+        let mut peaks = vec![0.001; windows(lead)];
+        peaks.extend(vec![0.5; windows(sound)]);
+        peaks.extend(vec![0.0; windows(tail)]);
+        (peaks, lead + sound + tail)
+    }
+    
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("cue point");
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+    
+    #[test]
+    fn long_silence_is_cut_at_both_ends() {
+        let (peaks, duration) = track(1.0, 2.0, 1.5);
+        let (cue_in, cue_out) = cue_points(&peaks, duration, SILENCE);
+        assert_near(cue_in, 1.0);
+        assert_near(cue_out, 3.0);
+    }
+    
+    #[test]
+    fn short_silence_is_left_alone() {
+        let (peaks, duration) = track(0.3, 2.0, 0.49);
+        assert_eq!(cue_points(&peaks, duration, SILENCE), (None, None));
+        
+        let (peaks, duration) = track(0.0, 2.0, 0.8);
+        let (cue_in, cue_out) = cue_points(&peaks, duration, SILENCE);
+        assert_eq!(cue_in, None);
+        assert_near(cue_out, 2.0);
+    }
+    
+    #[test]
+    fn silent_tracks_get_no_cue_points() {
+        let (peaks, duration) = track(3.0, 0.0, 0.0);
+        assert_eq!(cue_points(&peaks, duration, SILENCE), (None, None));
+        assert_eq!(cue_points(&[], 0.0, SILENCE), (None, None));
+    }
+    
+    #[test]
+    fn cue_out_never_passes_the_end() {
+        // The last window can run past the decoded audio
+        let (peaks, _) = track(1.0, 1.0, 0.0);
+        let (cue_in, cue_out) = cue_points(&peaks, 1.995, SILENCE);
+        assert_near(cue_in, 1.0);
+        assert_eq!(cue_out, None);
+    }
+}
@@ -0,0 +1,41 @@
+use actix_web::{HttpRequest, HttpResponse};
+
+/// Compare without returning early, so the token can't be guessed byte by byte from timings
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Check the admin token sent as `Authorization: Bearer <token>` or `X-Admin-Token`
+///
+/// Admin endpoints are disabled unless ADMIN_TOKEN is set.
+/// Environment variable: ADMIN_TOKEN
+pub fn require_admin(req: &HttpRequest) -> Result<(), HttpResponse> {
+    let expected = match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.trim().is_empty() => token.trim().to_string(),
+        _ => {
+            return Err(HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "Admin API is disabled (ADMIN_TOKEN is not set)"
+            })));
+        }
+    };
+    
+    let headers = req.headers();
+    let provided = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("X-Admin-Token").and_then(|v| v.to_str().ok()))
+        .map(str::trim);
+    
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        Some(_) => Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Invalid admin token"
+        }))),
+        None => Err(HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(serde_json::json!({
+                "error": "Admin token required"
+            }))),
+    }
+}
@@ -0,0 +1,192 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Result};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::api::upload::extract_username;
+use crate::library::match_import_entry;
+use crate::mpd_manager::{get_queue, QueueError};
+use crate::playlist_formats::{parse, render, ExportEntry, PlaylistFormat};
+use crate::quota::request_track;
+use crate::state::AppState;
+
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+const MAX_HISTORY_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// `m3u8` (default), `xspf` or `json`
+    pub format: Option<String>,
+    /// History only: number of most recent plays
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Detected from the body when missing
+    pub format: Option<String>,
+}
+
+fn parse_format(value: Option<&str>) -> std::result::Result<Option<PlaylistFormat>, HttpResponse> {
+    match value {
+        None => Ok(None),
+        Some(value) => PlaylistFormat::parse(value).map(Some).ok_or_else(|| {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid format, expected m3u8, xspf or json"
+            }))
+        }),
+    }
+}
+
+fn export_response(entries: &[ExportEntry], format: PlaylistFormat, name: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", name, format.extension()),
+        ))
+        .body(render(entries, format, &format!("Muchas Radio {}", name)))
+}
+
+async fn file_hash(state: &AppState, filename: &str) -> Option<String> {
+    state.storage.get(filename).await.map(|file| file.content_hash)
+}
+
+/// Upcoming queue as a playlist file
+#[get("/api/queue/export")]
+pub async fn export_queue(
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let format = match parse_format(query.format.as_deref()) {
+        Ok(format) => format.unwrap_or(PlaylistFormat::M3u8),
+        Err(response) => return Ok(response),
+    };
+    
+    let queue = match get_queue(&state).await {
+        Ok(queue) => queue,
+        Err(e) => {
+            error!("Failed to get queue: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e
+            })));
+        }
+    };
+    
+    let mut entries = Vec::with_capacity(queue.len());
+    for item in &queue {
+        let hash = file_hash(&state, &item.track.filename).await;
+        entries.push(ExportEntry::new(&item.track, hash, None));
+    }
+    Ok(export_response(&entries, format, "queue"))
+}
+
+/// Recently played tracks, newest first
+#[get("/api/history")]
+pub async fn get_history(
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    Ok(HttpResponse::Ok().json(state.history.recent(limit).await))
+}
+
+/// Play history as a playlist file, newest first
+#[get("/api/history/export")]
+pub async fn export_history(
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let format = match parse_format(query.format.as_deref()) {
+        Ok(format) => format.unwrap_or(PlaylistFormat::M3u8),
+        Err(response) => return Ok(response),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    
+    let history = state.history.recent(limit).await;
+    let mut entries = Vec::with_capacity(history.len());
+    for entry in &history {
+        let hash = file_hash(&state, &entry.track.filename).await;
+        entries.push(ExportEntry::new(&entry.track, hash, Some(entry.played_at)));
+    }
+    Ok(export_response(&entries, format, "history"))
+}
+
+/// Queue the catalog tracks an M3U, XSPF or JSON playlist refers to
+///
+/// Entries are matched by path, then file hash, then approximate artist and title.
+/// Matches go through the same checks as `POST /api/queue/add`.
+#[post("/api/queue/import")]
+pub async fn import_queue(
+    state: web::Data<AppState>,
+    query: web::Query<ImportQuery>,
+    body: String,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let username = extract_username(&req);
+    let format = match parse_format(query.format.as_deref()) {
+        Ok(format) => format.unwrap_or_else(|| PlaylistFormat::detect(&body)),
+        Err(response) => return Ok(response),
+    };
+    
+    let entries = match parse(&body, format) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            })));
+        }
+    };
+    
+    let mut queued = Vec::new();
+    let mut rejected = Vec::new();
+    let mut missing = Vec::new();
+    for entry in entries {
+        let Some(track) = match_import_entry(&state, &entry).await else {
+            missing.push(entry);
+            continue;
+        };
+        
+        match request_track(&state, &username, &track.id, &track.filename).await {
+            Ok(()) => queued.push(serde_json::json!({
+                "entry": entry,
+                "track_id": track.id,
+                "artist": track.artist,
+                "title": track.title
+            })),
+            Err(QueueError::Rejected(rejection)) => rejected.push(serde_json::json!({
+                "entry": entry,
+                "track_id": track.id,
+                "reason": rejection.reason(),
+                "error": rejection.message()
+            })),
+            Err(e) => {
+                error!("Failed to queue imported track {}: {}", track.filename, e);
+                rejected.push(serde_json::json!({
+                    "entry": entry,
+                    "track_id": track.id,
+                    "reason": "queue_error",
+                    "error": e.to_string()
+                }));
+            }
+        }
+    }
+    
+    info!(
+        "Imported {:?} playlist for {}: {} queued, {} rejected, {} not found",
+        format, username, queued.len(), rejected.len(), missing.len()
+    );
+    if !queued.is_empty() {
+        let queue_update = serde_json::json!({
+            "type": "queue_update",
+            "data": {}
+        });
+        state.broadcast_message(&queue_update.to_string()).await;
+    }
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": !queued.is_empty(),
+        "queued": queued,
+        "rejected": rejected,
+        "missing": missing
+    })))
+}
@@ -0,0 +1,207 @@
+use actix_multipart::{Field, Multipart};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Result};
+use futures::{StreamExt, TryStreamExt};
+use log::{error, info, warn};
+use mpd_client::commands;
+use tokio::io::AsyncWriteExt;
+
+use crate::api::admin::require_admin;
+use crate::api::upload::{check_file_type, MAX_FILE_SIZE};
+use crate::audio::read_tags;
+use crate::jingles::{jingle_path, list_jingles, JingleRules, JINGLE_DIR};
+use crate::mpd_manager::update_database_path;
+use crate::state::AppState;
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({
+        "error": message.into()
+    }))
+}
+
+/// Jingle names are plain file names inside the jingle directory
+fn validate_name(name: &str) -> std::result::Result<String, HttpResponse> {
+    let sanitized = sanitize_filename::sanitize(name.trim());
+    if sanitized.is_empty() || sanitized.starts_with('.') || sanitized != name.trim() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "Invalid jingle name"));
+    }
+    Ok(sanitized)
+}
+
+/// Write one multipart field to the jingle directory and make sure it decodes
+async fn save_jingle(
+    state: &AppState,
+    field: &mut Field,
+    name: &str,
+) -> std::result::Result<(), HttpResponse> {
+    let dir = state.storage.path(JINGLE_DIR);
+    let path = dir.join(name);
+    let tmp_path = dir.join(format!(".{}.part", name));
+    let server_error = |message: &str| error_response(StatusCode::INTERNAL_SERVER_ERROR, message);
+    
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        error!("Failed to create jingle directory: {}", e);
+        return Err(server_error("Failed to save jingle"));
+    }
+    let mut file = tokio::fs::File::create(&tmp_path).await.map_err(|e| {
+        error!("Failed to create jingle file: {}", e);
+        server_error("Failed to save jingle")
+    })?;
+    
+    let mut total_size = 0usize;
+    while let Some(chunk) = field.next().await {
+        let written = match chunk {
+            Ok(data) => {
+                total_size += data.len();
+                if total_size > MAX_FILE_SIZE {
+                    let _ = tokio::fs::remove_file(&tmp_path).await;
+                    return Err(error_response(StatusCode::BAD_REQUEST, "File too large (max 100MB)"));
+                }
+                file.write_all(&data).await
+            }
+            Err(e) => Err(std::io::Error::other(e.to_string())),
+        };
+        if let Err(e) = written {
+            error!("Error saving jingle {}: {}", name, e);
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(server_error("Error saving file"));
+        }
+    }
+    if let Err(e) = file.flush().await {
+        error!("Error saving jingle {}: {}", name, e);
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(server_error("Error saving file"));
+    }
+    
+    let probe_path = tmp_path.clone();
+    match tokio::task::spawn_blocking(move || read_tags(&probe_path)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            warn!("Rejected jingle {}: {}", name, e);
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(error_response(StatusCode::BAD_REQUEST, "File is not playable audio"));
+        }
+        Err(e) => {
+            error!("Jingle check for {} failed: {}", name, e);
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(server_error("Failed to check jingle"));
+        }
+    }
+    
+    tokio::fs::rename(&tmp_path, &path).await.map_err(|e| {
+        error!("Failed to save jingle {}: {}", name, e);
+        server_error("Failed to save jingle")
+    })?;
+    
+    update_database_path(state, &jingle_path(name)).await.map_err(|e| {
+        error!("Failed to index jingle {}: {}", name, e);
+        server_error(&e.to_string())
+    })
+}
+
+/// Available jingles and the rules that insert them
+#[get("/api/admin/jingles")]
+pub async fn get_jingles(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    match list_jingles(&state).await {
+        Ok(names) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "jingles": names,
+            "rules": JingleRules::from_env()
+        }))),
+        Err(e) => {
+            error!("Failed to list jingles: {}", e);
+            Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list jingles"))
+        }
+    }
+}
+
+/// Upload jingle files (multipart); a file with an existing name replaces it
+#[post("/api/admin/jingles")]
+pub async fn upload_jingles(
+    mut payload: Multipart,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    let mut saved = Vec::new();
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let Some(filename) = field.content_disposition().and_then(|cd| cd.get_filename()).map(str::to_string) else {
+            // Not a file; skip its contents
+            while let Some(Ok(_)) = field.next().await {}
+            continue;
+        };
+        
+        if let Err(e) = check_file_type(&filename) {
+            return Ok(e.into_response());
+        }
+        let name = sanitize_filename::sanitize(&filename);
+        if name.is_empty() || name.starts_with('.') {
+            return Ok(error_response(StatusCode::BAD_REQUEST, "Invalid jingle name"));
+        }
+        if let Err(response) = save_jingle(&state, &mut field, &name).await {
+            return Ok(response);
+        }
+        info!("Saved jingle {}", name);
+        saved.push(name);
+    }
+    
+    if saved.is_empty() {
+        return Ok(error_response(StatusCode::BAD_REQUEST, "No file uploaded"));
+    }
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "jingles": saved
+    })))
+}
+
+/// Delete a jingle and drop it from the queue unless it's on air
+#[delete("/api/admin/jingles/{name}")]
+pub async fn delete_jingle(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let name = match validate_name(&path) {
+        Ok(name) => name,
+        Err(response) => return Ok(response),
+    };
+    
+    match tokio::fs::remove_file(state.storage.path(JINGLE_DIR).join(&name)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(error_response(StatusCode::NOT_FOUND, "Jingle not found"));
+        }
+        Err(e) => {
+            error!("Failed to delete jingle {}: {}", name, e);
+            return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete jingle"));
+        }
+    }
+    
+    let uri = jingle_path(&name);
+    {
+        let client = state.mpd_client.lock().await;
+        let current_id = client.command(commands::Status).await.ok()
+            .and_then(|status| status.current_song.map(|(_, id)| id));
+        if let Ok(queue) = client.command(commands::Queue).await {
+            for song in queue.iter().filter(|s| s.song.url == uri && Some(s.id) != current_id) {
+                if let Err(e) = client.command(commands::Delete::id(song.id)).await {
+                    warn!("Failed to remove jingle {} from queue: {}", name, e);
+                }
+            }
+        }
+    }
+    if let Err(e) = update_database_path(&state, &uri).await {
+        warn!("Failed to update MPD database after deleting jingle {}: {}", name, e);
+    }
+    
+    info!("Deleted jingle {}", name);
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,4 +1,15 @@
+pub mod admin;
 pub mod upload;
+pub mod resumable;
 pub mod playlist;
+pub mod playback;
+pub mod export;
+pub mod jingles;
+pub mod saved_playlists;
+pub mod programming;
 pub mod stream;
+pub mod quota;
+pub mod settings;
+pub mod tracks;
+pub mod storage;
 
@@ -0,0 +1,113 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Result};
+use log::error;
+
+use crate::api::admin::require_admin;
+use crate::models::SeekRequest;
+use crate::mpd_manager::{
+    broadcast_current_track, get_current_track, play_previous, seek_current, set_paused, stop_playback,
+};
+use crate::state::AppState;
+
+/// Broadcast the new state to every client, or report the MPD failure
+async fn transport_response(state: &AppState, result: std::result::Result<(), String>) -> HttpResponse {
+    match result {
+        Ok(()) => {
+            broadcast_current_track(state).await;
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true
+            }))
+        }
+        Err(e) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e
+            }))
+        }
+    }
+}
+
+/// Pause playback; nothing resumes it until an admin does
+#[post("/api/admin/playback/pause")]
+pub async fn pause(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let result = set_paused(&state, true).await;
+    Ok(transport_response(&state, result).await)
+}
+
+/// Resume after a pause or stop
+#[post("/api/admin/playback/resume")]
+pub async fn resume(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let result = set_paused(&state, false).await;
+    Ok(transport_response(&state, result).await)
+}
+
+/// Stop playback; unlike a finished queue, it isn't restarted automatically
+#[post("/api/admin/playback/stop")]
+pub async fn stop(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let result = stop_playback(&state).await;
+    Ok(transport_response(&state, result).await)
+}
+
+/// Jump to `position` seconds into the current track
+#[post("/api/admin/playback/seek")]
+pub async fn seek(
+    state: web::Data<AppState>,
+    body: web::Json<SeekRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    let current = match get_current_track(&state).await {
+        Ok(current) => current,
+        Err(e) => return Ok(transport_response(&state, Err(e)).await),
+    };
+    let Some(track) = current.track else {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Nothing is playing"
+        })));
+    };
+    let in_range = body.position.is_finite()
+        && body.position >= 0.0
+        && track.duration.is_none_or(|duration| body.position < duration);
+    if !in_range {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Position is outside the current track"
+        })));
+    }
+    
+    let result = seek_current(&state, std::time::Duration::from_secs_f64(body.position)).await;
+    Ok(transport_response(&state, result).await)
+}
+
+/// Play the previously played track again
+#[post("/api/admin/playback/previous")]
+pub async fn previous(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    match play_previous(&state).await {
+        Ok(true) => {
+            let queue_update = serde_json::json!({
+                "type": "queue_update",
+                "data": {}
+            });
+            state.broadcast_message(&queue_update.to_string()).await;
+            Ok(transport_response(&state, Ok(())).await)
+        }
+        Ok(false) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "The previous track is no longer in the queue"
+        }))),
+        Err(e) => Ok(transport_response(&state, Err(e)).await),
+    }
+}
@@ -1,10 +1,39 @@
-use actix_web::{get, post, web, HttpResponse, Result};
-use log::error;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Result};
+use log::{error, info};
 
+use crate::api::upload::extract_username;
 use crate::models::AddToQueueRequest;
-use crate::mpd_manager::{add_file_to_mpd, get_current_track, get_queue, start_playback};
+use crate::mpd_manager::{QueueError, get_current_track, get_queue, start_playback};
+use crate::quota::{get_quota_status, request_track, RequestRejected};
 use crate::state::AppState;
 
+async fn request_rejected_response(state: &AppState, username: &str, rejected: RequestRejected) -> HttpResponse {
+    match rejected {
+        RequestRejected::TooManyPending => {
+            let quota = get_quota_status(state, username).await;
+            HttpResponse::TooManyRequests().json(serde_json::json!({
+                "error": rejected.message(),
+                "reason": rejected.reason(),
+                "quota": quota
+            }))
+        }
+        RequestRejected::AlreadyQueued => HttpResponse::Conflict().json(serde_json::json!({
+            "error": rejected.message(),
+            "reason": rejected.reason()
+        })),
+        RequestRejected::Cooldown(available_at) => {
+            let retry_after = (available_at - chrono::Utc::now()).num_seconds().max(1);
+            HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({
+                    "error": rejected.message(),
+                    "reason": rejected.reason(),
+                    "available_at": available_at
+                }))
+        }
+    }
+}
+
 #[get("/api/current")]
 pub async fn get_current(state: web::Data<AppState>) -> Result<HttpResponse> {
     match get_current_track(&state).await {
@@ -35,8 +64,11 @@ pub async fn get_queue_list(state: web::Data<AppState>) -> Result<HttpResponse>
 pub async fn add_to_queue(
     state: web::Data<AppState>,
     request: web::Json<AddToQueueRequest>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
-    // Find the track in metadata
+    let username = extract_username(&req);
+    
+    // Find the track in the catalog
     let metadata = state.tracks_metadata.read().await;
     let track = match metadata.get(&request.track_id) {
         Some(t) => t.clone(),
@@ -46,8 +78,9 @@ pub async fn add_to_queue(
             })));
         }
     };
+    drop(metadata);
     
-    match add_file_to_mpd(&state, &track.filename).await {
+    match request_track(&state, &username, &track.id, &track.filename).await {
         Ok(_) => {
             // Notify via WebSocket
             let queue_update = serde_json::json!({
@@ -60,10 +93,19 @@ pub async fn add_to_queue(
                 "success": true
             })))
         }
+        Err(QueueError::Rejected(rejected)) => {
+            info!("Rejected request for {} by {}: {}", track.filename, username, rejected.reason());
+            Ok(request_rejected_response(&state, &username, rejected).await)
+        }
         Err(e) => {
             error!("Failed to add to queue: {}", e);
-            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": e
+            let mut response = match e {
+                QueueError::NotInDatabase(_) => HttpResponse::NotFound(),
+                QueueError::UpdateTimedOut(_) => HttpResponse::GatewayTimeout(),
+                QueueError::Mpd(_) | QueueError::Rejected(_) => HttpResponse::InternalServerError(),
+            };
+            Ok(response.json(serde_json::json!({
+                "error": e.to_string()
             })))
         }
     }
@@ -71,6 +113,12 @@ pub async fn add_to_queue(
 
 #[post("/api/play")]
 pub async fn play(state: web::Data<AppState>) -> Result<HttpResponse> {
+    if state.is_playback_held() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Playback was paused by an admin"
+        })));
+    }
+    
     match start_playback(&state).await {
         Ok(_) => {
             // Notify via WebSocket
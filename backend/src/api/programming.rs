@@ -0,0 +1,94 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Result};
+use log::{error, info};
+
+use crate::api::admin::require_admin;
+use crate::programming::{ShowError, ShowInput};
+use crate::state::AppState;
+
+/// Validation problems are 400s, overlaps 409s and anything else a failed save
+fn show_error_response(e: ShowError) -> HttpResponse {
+    let body = serde_json::json!({ "error": e.to_string() });
+    match e {
+        ShowError::Invalid(_) => HttpResponse::BadRequest().json(body),
+        ShowError::Overlap(_) => HttpResponse::Conflict().json(body),
+        ShowError::Io(_) => {
+            error!("{}", e);
+            HttpResponse::InternalServerError().json(body)
+        }
+    }
+}
+
+/// All scheduled shows
+#[get("/api/admin/shows")]
+pub async fn list_shows(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    Ok(HttpResponse::Ok().json(state.programming.shows().await))
+}
+
+/// Add a weekly slot
+#[post("/api/admin/shows")]
+pub async fn create_show(
+    state: web::Data<AppState>,
+    body: web::Json<ShowInput>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    match state.programming.create(body.into_inner()).await {
+        Ok(show) => {
+            info!("Scheduled show {} ({})", show.input.name, show.id);
+            Ok(HttpResponse::Created().json(show))
+        }
+        Err(e) => Ok(show_error_response(e)),
+    }
+}
+
+/// Replace a show; a show on air ends early if its new slot no longer covers now
+#[put("/api/admin/shows/{id}")]
+pub async fn update_show(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ShowInput>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    match state.programming.update(&path, body.into_inner()).await {
+        Ok(Some(show)) => Ok(HttpResponse::Ok().json(show)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Show not found"
+        }))),
+        Err(e) => Ok(show_error_response(e)),
+    }
+}
+
+#[delete("/api/admin/shows/{id}")]
+pub async fn delete_show(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    match state.programming.delete(&path).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Show not found"
+        }))),
+        Err(e) => Ok(show_error_response(e)),
+    }
+}
+
+/// The show on air, or null
+#[get("/api/shows/current")]
+pub async fn current_show(state: web::Data<AppState>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.programming.active().await))
+}
@@ -0,0 +1,15 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Result};
+
+use crate::api::upload::extract_username;
+use crate::quota::get_quota_status;
+use crate::state::AppState;
+
+#[get("/api/me/quota")]
+pub async fn get_my_quota(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let username = extract_username(&req);
+    let status = get_quota_status(&state, &username).await;
+    Ok(HttpResponse::Ok().json(status))
+}
@@ -0,0 +1,338 @@
+use actix_web::{delete, patch, post, route, web, HttpRequest, HttpResponse, Result};
+use futures::StreamExt;
+use log::{error, info, warn};
+use std::io::SeekFrom;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::api::upload::{check_upload_quota, extract_username, ingest_upload, stored_filename};
+use crate::jobs::{fail_job, report_progress, set_job_state, start_job, update_job, PROGRESS_STEP_BYTES};
+use crate::models::{CreateResumableUpload, UploadJobState};
+use crate::resumable::{CreateSessionError, UploadSession};
+use crate::state::AppState;
+use crate::storage::hash_file;
+
+/// Look up a session and make sure it belongs to the requesting user
+async fn find_session(
+    state: &AppState,
+    req: &HttpRequest,
+    upload_id: &str,
+) -> std::result::Result<std::sync::Arc<tokio::sync::Mutex<UploadSession>>, HttpResponse> {
+    let handle = state.resumable_uploads.get(upload_id).await.ok_or_else(|| {
+        HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Upload not found or expired"
+        }))
+    })?;
+    
+    if handle.owner != extract_username(req) {
+        return Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Upload belongs to another user"
+        })));
+    }
+    Ok(handle.session)
+}
+
+fn offset_mismatch(session: &UploadSession) -> HttpResponse {
+    HttpResponse::Conflict()
+        .insert_header(("Upload-Offset", session.offset.to_string()))
+        .json(serde_json::json!({
+            "error": "Upload-Offset does not match the server offset",
+            "offset": session.offset
+        }))
+}
+
+/// Start a resumable upload; quotas are checked against the declared size plus the
+/// declared sizes of the user's other unfinished uploads
+///
+/// Room in shared storage is only made at finalize, so abandoned uploads never cost
+/// the library any tracks.
+#[post("/api/upload/resumable")]
+pub async fn create_upload(
+    body: web::Json<CreateResumableUpload>,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let username = extract_username(&req);
+    
+    if body.size == 0 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "File is empty"
+        })));
+    }
+    let staged = state.resumable_uploads.staged_bytes(&username, None).await;
+    if let Err(e) = check_upload_quota(&state, &username, &body.filename, Some(body.size), staged).await {
+        return Ok(e.into_response());
+    }
+    
+    match state.resumable_uploads.create(&username, &body.filename, body.size, body.sha256.clone()).await {
+        Ok(session) => {
+            start_job(&state, Some(session.id.clone()), &username, &session.filename, Some(session.size)).await;
+            info!("Started resumable upload {} of {} ({} bytes) for {}", session.id, session.filename, session.size, username);
+            Ok(HttpResponse::Created()
+                .insert_header(("Location", format!("/api/upload/resumable/{}", session.id)))
+                .insert_header(("Upload-Offset", "0"))
+                .json(state.resumable_uploads.status(&session)))
+        }
+        Err(CreateSessionError::TooManySessions(max)) => {
+            warn!("Upload from {} rejected: too many unfinished uploads", username);
+            Ok(HttpResponse::TooManyRequests().json(serde_json::json!({
+                "error": format!("Too many unfinished uploads (max {}); finish or cancel one first", max)
+            })))
+        }
+        Err(CreateSessionError::Io(e)) => {
+            error!("Failed to create upload session: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to create upload"
+            })))
+        }
+    }
+}
+
+/// Current offset of an upload, so clients know where to resume
+#[route("/api/upload/resumable/{upload_id}", method = "GET", method = "HEAD")]
+pub async fn upload_status(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let session = match find_session(&state, &req, &path).await {
+        Ok(session) => session,
+        Err(response) => return Ok(response),
+    };
+    let session = session.lock().await;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(("Upload-Offset", session.offset.to_string()))
+        .insert_header(("Upload-Length", session.size.to_string()))
+        .insert_header(("Cache-Control", "no-store"))
+        .json(state.resumable_uploads.status(&session)))
+}
+
+/// Append the request body at `Upload-Offset`
+///
+/// Whatever arrives before the connection drops is kept, and the client resumes
+/// from the offset reported by `GET`.
+#[patch("/api/upload/resumable/{upload_id}")]
+pub async fn append_chunk(
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let session = match find_session(&state, &req, &path).await {
+        Ok(session) => session,
+        Err(response) => return Ok(response),
+    };
+    // One writer per upload; a second concurrent PATCH would interleave bytes
+    let mut session = match session.try_lock() {
+        Ok(session) => session,
+        Err(_) => {
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Another request is already uploading to this upload"
+            })));
+        }
+    };
+    
+    let client_offset = req.headers()
+        .get("Upload-Offset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    match client_offset {
+        Some(offset) if offset == session.offset => {}
+        Some(_) => return Ok(offset_mismatch(&session)),
+        None => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Missing or invalid Upload-Offset header"
+            })));
+        }
+    }
+    
+    let part_path = state.resumable_uploads.part_path(&session.id);
+    let mut file = match tokio::fs::OpenOptions::new().write(true).open(&part_path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open partial upload {:?}: {}", part_path, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to open upload"
+            })));
+        }
+    };
+    // Drop any bytes past the acknowledged offset, e.g. from a write cut short
+    if let Err(e) = async {
+        file.set_len(session.offset).await?;
+        file.seek(SeekFrom::Start(session.offset)).await
+    }.await {
+        error!("Failed to prepare partial upload {:?}: {}", part_path, e);
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to open upload"
+        })));
+    }
+    
+    let mut reported_offset = session.offset;
+    let mut response = None;
+    while let Some(chunk) = payload.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Upload {} interrupted at {} bytes: {}", session.id, session.offset, e);
+                break;
+            }
+        };
+        
+        if session.offset + data.len() as u64 > session.size {
+            response = Some(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": "Chunk goes past the declared file size"
+            })));
+            break;
+        }
+        
+        if let Err(e) = file.write_all(&data).await {
+            error!("Error writing upload {}: {}", session.id, e);
+            response = Some(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Error saving chunk"
+            })));
+            break;
+        }
+        session.offset += data.len() as u64;
+        
+        if session.offset - reported_offset >= PROGRESS_STEP_BYTES {
+            reported_offset = session.offset;
+            report_progress(&state, &session.id, reported_offset).await;
+        }
+    }
+    
+    if let Err(e) = file.flush().await {
+        error!("Error writing upload {}: {}", session.id, e);
+    }
+    session.updated_at = chrono::Utc::now();
+    if let Err(e) = state.resumable_uploads.save(&session).await {
+        warn!("Failed to persist upload {}: {}", session.id, e);
+    }
+    let offset = session.offset;
+    update_job(&state, &session.id, |job| job.bytes_received = offset).await;
+    
+    Ok(response.unwrap_or_else(|| {
+        HttpResponse::NoContent()
+            .insert_header(("Upload-Offset", session.offset.to_string()))
+            .finish()
+    }))
+}
+
+/// Verify a complete upload and hand it to the regular upload pipeline
+#[post("/api/upload/resumable/{upload_id}/finalize")]
+pub async fn finalize_upload(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let session = match find_session(&state, &req, &path).await {
+        Ok(session) => session,
+        Err(response) => return Ok(response),
+    };
+    // Held until the upload is ingested, so a concurrent finalize can't ingest it twice
+    let guard = match session.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Upload is still receiving data or being finalized"
+            })));
+        }
+    };
+    let session = guard.clone();
+    
+    if session.offset != session.size {
+        return Ok(HttpResponse::Conflict()
+            .insert_header(("Upload-Offset", session.offset.to_string()))
+            .json(serde_json::json!({
+                "error": format!("Upload incomplete: {} of {} bytes received", session.offset, session.size),
+                "offset": session.offset
+            })));
+    }
+    
+    set_job_state(&state, &session.id, UploadJobState::Validating).await;
+    let part_path = state.resumable_uploads.part_path(&session.id);
+    let content_hash = match hash_file(&part_path).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Failed to hash upload {}: {}", session.id, e);
+            fail_job(&state, &session.id, "Failed to read upload").await;
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to read upload"
+            })));
+        }
+    };
+    
+    // A corrupted transfer can't be repaired by resuming; the client has to start over
+    if session.sha256.as_ref().is_some_and(|expected| *expected != content_hash) {
+        warn!("Upload {} failed integrity check", session.id);
+        state.resumable_uploads.discard(&session.id).await;
+        fail_job(&state, &session.id, "Checksum mismatch").await;
+        return Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "Checksum mismatch, upload discarded",
+            "expected": session.sha256,
+            "actual": content_hash
+        })));
+    }
+    
+    // Limits may have been reached since the upload was created; room in shared storage
+    // is made during ingest, once the file is known not to be a duplicate
+    let staged = state.resumable_uploads.staged_bytes(&session.username, Some(&session.id)).await;
+    if let Err(e) = check_upload_quota(&state, &session.username, &session.filename, Some(session.size), staged).await {
+        fail_job(&state, &session.id, e.message()).await;
+        return Ok(e.into_response());
+    }
+    
+    let track_id = Uuid::new_v4().to_string();
+    let final_filename = stored_filename(&track_id, &session.username, &session.filename);
+    let final_path = state.storage.path(&final_filename);
+    
+    // The staging directory may be on another filesystem, where rename fails
+    state.storage.start_write(&final_filename).await;
+    let moved = match tokio::fs::rename(&part_path, &final_path).await {
+        Ok(()) => Ok(()),
+        Err(_) => tokio::fs::copy(&part_path, &final_path).await.map(|_| ()),
+    };
+    if let Err(e) = moved {
+        error!("Failed to move upload {} into storage: {}", session.id, e);
+        let _ = state.storage.remove(&final_filename).await;
+        fail_job(&state, &session.id, "Failed to save file").await;
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to save file"
+        })));
+    }
+    state.resumable_uploads.discard(&session.id).await;
+    
+    info!("Resumable upload {} finalized as {}", session.id, final_filename);
+    
+    match ingest_upload(&state, &session.id, &session.username, track_id, &session.filename, final_filename, content_hash).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+/// Abandon an upload and delete its partial data
+#[delete("/api/upload/resumable/{upload_id}")]
+pub async fn cancel_upload(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let session = match find_session(&state, &req, &path).await {
+        Ok(session) => session,
+        Err(response) => return Ok(response),
+    };
+    // The same lock PATCH and finalize hold, so the part file isn't deleted under them
+    let _guard = match session.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Upload is still receiving data or being finalized"
+            })));
+        }
+    };
+    
+    state.resumable_uploads.discard(&path).await;
+    fail_job(&state, &path, "Upload cancelled").await;
+    info!("Cancelled resumable upload {}", path.as_str());
+    Ok(HttpResponse::NoContent().finish())
+}
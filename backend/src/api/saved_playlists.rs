@@ -0,0 +1,325 @@
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Result};
+use log::{error, info};
+use mpd_client::client::CommandError;
+use mpd_client::commands;
+use mpd_client::commands::{SongId, SongPosition};
+use mpd_client::responses::PlayState;
+use std::collections::HashSet;
+
+use crate::api::admin::require_admin;
+use crate::models::{
+    AddToQueueRequest, LoadPlaylistRequest, PlaylistLoadMode, PlaylistNameRequest, SavedPlaylist, SavedPlaylistInfo,
+};
+use crate::mpd_manager::{apply_cue_range, song_to_track, sync_queue_schedule, ACK_ERROR_NO_EXIST};
+use crate::state::AppState;
+
+/// MPD's ACK codes for a bad argument and an already existing playlist
+const ACK_ERROR_ARG: u64 = 2;
+const ACK_ERROR_EXIST: u64 = 56;
+const MAX_PLAYLIST_NAME_LEN: usize = 100;
+
+/// Playlist names become file names in MPD's playlist directory
+fn validate_name(name: &str) -> std::result::Result<String, HttpResponse> {
+    let name = name.trim();
+    let invalid = name.is_empty()
+        || name.chars().count() > MAX_PLAYLIST_NAME_LEN
+        || name.starts_with('.')
+        || name.chars().any(|c| c == '/' || c == '\\' || c.is_control());
+    if invalid {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid playlist name (1-{} characters, no slashes, not starting with a dot)", MAX_PLAYLIST_NAME_LEN)
+        })));
+    }
+    Ok(name.to_string())
+}
+
+fn mpd_error_response(e: CommandError, action: &str) -> HttpResponse {
+    match e {
+        CommandError::ErrorResponse { error, .. } if error.code == ACK_ERROR_NO_EXIST => {
+            HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Playlist not found"
+            }))
+        }
+        CommandError::ErrorResponse { error, .. } if error.code == ACK_ERROR_EXIST => {
+            HttpResponse::Conflict().json(serde_json::json!({
+                "error": "A playlist with that name already exists"
+            }))
+        }
+        CommandError::ErrorResponse { error, .. } if error.code == ACK_ERROR_ARG => {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": error.message.to_string()
+            }))
+        }
+        e => {
+            error!("Failed to {}: {}", action, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Failed to {}", action)
+            }))
+        }
+    }
+}
+
+async fn broadcast_queue_update(state: &AppState) {
+    let queue_update = serde_json::json!({
+        "type": "queue_update",
+        "data": {}
+    });
+    state.broadcast_message(&queue_update.to_string()).await;
+}
+
+#[get("/api/playlists")]
+pub async fn list_playlists(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let client = state.mpd_client.lock().await;
+    match client.command(commands::GetPlaylists).await {
+        Ok(playlists) => {
+            let mut playlists: Vec<SavedPlaylistInfo> = playlists
+                .into_iter()
+                .map(|p| SavedPlaylistInfo {
+                    name: p.name,
+                    last_modified: p.last_modified.raw().to_string(),
+                })
+                .collect();
+            playlists.sort_by_key(|p| p.name.to_lowercase());
+            Ok(HttpResponse::Ok().json(playlists))
+        }
+        Err(e) => Ok(mpd_error_response(e, "list playlists")),
+    }
+}
+
+/// Save the whole current queue as a new playlist
+#[post("/api/playlists")]
+pub async fn create_playlist(
+    state: web::Data<AppState>,
+    body: web::Json<PlaylistNameRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let name = match validate_name(&body.name) {
+        Ok(name) => name,
+        Err(response) => return Ok(response),
+    };
+    
+    let client = state.mpd_client.lock().await;
+    match client.command(commands::SaveQueueAsPlaylist(&name)).await {
+        Ok(()) => {
+            info!("Saved queue as playlist {}", name);
+            Ok(HttpResponse::Created().json(serde_json::json!({
+                "success": true,
+                "name": name
+            })))
+        }
+        Err(e) => Ok(mpd_error_response(e, "save playlist")),
+    }
+}
+
+#[get("/api/playlists/{name}")]
+pub async fn get_playlist(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let name = path.into_inner();
+    let songs = {
+        let client = state.mpd_client.lock().await;
+        match client.command(commands::GetPlaylist(&name)).await {
+            Ok(songs) => songs,
+            Err(e) => return Ok(mpd_error_response(e, "read playlist")),
+        }
+    };
+    
+    let mut tracks = Vec::with_capacity(songs.len());
+    for song in &songs {
+        tracks.push(song_to_track(song, &state, None).await);
+    }
+    Ok(HttpResponse::Ok().json(SavedPlaylist { name, tracks }))
+}
+
+#[patch("/api/playlists/{name}")]
+pub async fn rename_playlist(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<PlaylistNameRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let new_name = match validate_name(&body.name) {
+        Ok(name) => name,
+        Err(response) => return Ok(response),
+    };
+    
+    let client = state.mpd_client.lock().await;
+    match client.command(commands::RenamePlaylist::new(&path, &new_name)).await {
+        Ok(()) => {
+            info!("Renamed playlist {} to {}", path.as_str(), new_name);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "name": new_name
+            })))
+        }
+        Err(e) => Ok(mpd_error_response(e, "rename playlist")),
+    }
+}
+
+#[delete("/api/playlists/{name}")]
+pub async fn delete_playlist(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let client = state.mpd_client.lock().await;
+    match client.command(commands::DeletePlaylist(&path)).await {
+        Ok(()) => {
+            info!("Deleted playlist {}", path.as_str());
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => Ok(mpd_error_response(e, "delete playlist")),
+    }
+}
+
+/// Append a library track to a playlist
+#[post("/api/playlists/{name}/tracks")]
+pub async fn add_playlist_track(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<AddToQueueRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let filename = match state.tracks_metadata.read().await.get(&body.track_id) {
+        Some(track) => track.filename.clone(),
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Track not found"
+            })));
+        }
+    };
+    
+    let client = state.mpd_client.lock().await;
+    // MPD would silently create a missing playlist
+    if let Err(e) = client.command(commands::GetPlaylist(&path)).await {
+        return Ok(mpd_error_response(e, "read playlist"));
+    }
+    match client.command(commands::AddToPlaylist::new(&path, &filename)).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true
+        }))),
+        Err(e) => Ok(mpd_error_response(e, "add track to playlist")),
+    }
+}
+
+/// Remove the track at a 0-based position from a playlist
+#[delete("/api/playlists/{name}/tracks/{position}")]
+pub async fn remove_playlist_track(
+    state: web::Data<AppState>,
+    path: web::Path<(String, usize)>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    let (name, position) = path.into_inner();
+    
+    let client = state.mpd_client.lock().await;
+    match client.command(commands::RemoveFromPlaylist::position(&name, position)).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(mpd_error_response(e, "remove track from playlist")),
+    }
+}
+
+/// Queue a playlist right after the pending requests
+///
+/// `replace` first drops the other upcoming tracks and needs the admin token; requests
+/// already waiting are kept.
+#[post("/api/playlists/{name}/load")]
+pub async fn load_playlist(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: Option<web::Json<LoadPlaylistRequest>>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let mode = body.map(|b| b.mode).unwrap_or_default();
+    // Appending only adds tracks, replacing drops what listeners queued up
+    if mode == PlaylistLoadMode::Replace {
+        if let Err(response) = require_admin(&req) {
+            return Ok(response);
+        }
+    }
+    let client = state.mpd_client.lock().await;
+    
+    let (status, queue) = match (client.command(commands::Status).await, client.command(commands::Queue).await) {
+        (Ok(status), Ok(queue)) => (status, queue),
+        (Err(e), _) | (_, Err(e)) => return Ok(mpd_error_response(e, "read queue")),
+    };
+    let current_id = status.current_song.map(|(_, id)| id);
+    let pending: HashSet<SongId> = state.pending_requests.lock().await.iter().map(|r| r.song_id).collect();
+    
+    // Everything up to the last pending request stays ahead of the playlist
+    let upcoming_start = status.current_song.map(|(pos, _)| pos.0 + 1).unwrap_or(0);
+    if mode == PlaylistLoadMode::Replace {
+        for song in queue.iter().skip(upcoming_start) {
+            if Some(song.id) == current_id || pending.contains(&song.id) {
+                continue;
+            }
+            if let Err(e) = client.command(commands::Delete::id(song.id)).await {
+                return Ok(mpd_error_response(e, "clear upcoming tracks"));
+            }
+        }
+    }
+    
+    let before: HashSet<SongId> = match client.command(commands::Queue).await {
+        Ok(queue) => queue.iter().map(|s| s.id).collect(),
+        Err(e) => return Ok(mpd_error_response(e, "read queue")),
+    };
+    if let Err(e) = client.command(commands::LoadPlaylist::name(&path)).await {
+        return Ok(mpd_error_response(e, "load playlist"));
+    }
+    let queue = match client.command(commands::Queue).await {
+        Ok(queue) => queue,
+        Err(e) => return Ok(mpd_error_response(e, "read queue")),
+    };
+    
+    let insert_at = queue.iter().filter(|s| before.contains(&s.id)).enumerate()
+        .filter(|(_, s)| Some(s.id) == current_id || pending.contains(&s.id))
+        .map(|(pos, _)| pos + 1)
+        .last()
+        .unwrap_or(upcoming_start);
+    let loaded: Vec<(SongId, String)> = queue.iter()
+        .filter(|s| !before.contains(&s.id))
+        .map(|s| (s.id, s.song.url.clone()))
+        .collect();
+    
+    for (offset, (song_id, filename)) in loaded.iter().enumerate() {
+        if let Err(e) = client.command(commands::Move::id(*song_id).to_position(SongPosition(insert_at + offset))).await {
+            return Ok(mpd_error_response(e, "position playlist tracks"));
+        }
+        apply_cue_range(&state, &client, *song_id, filename).await;
+    }
+    
+    if let Err(e) = sync_queue_schedule(&state, &client).await {
+        error!("Failed to sync queue schedule: {}", e);
+    }
+    if status.state == PlayState::Stopped && !state.is_playback_held() {
+        if let Some((song_id, _)) = loaded.first() {
+            if let Err(e) = client.command(commands::Play::song(*song_id)).await {
+                error!("Failed to start playback: {}", e);
+            }
+        }
+    }
+    drop(client);
+    
+    info!("Loaded playlist {} ({} tracks, {:?})", path.as_str(), loaded.len(), mode);
+    broadcast_queue_update(&state).await;
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "loaded": loaded.len()
+    })))
+}
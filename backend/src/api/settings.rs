@@ -0,0 +1,51 @@
+use actix_web::{get, patch, web, HttpRequest, HttpResponse, Result};
+use log::{error, info};
+
+use crate::api::admin::require_admin;
+use crate::settings::{PlaybackSettingsUpdate, SettingsError};
+use crate::state::AppState;
+
+/// Stored playback settings (crossfade, MixRamp, replay gain, random, repeat, consume)
+#[get("/api/admin/settings")]
+pub async fn get_settings(state: web::Data<AppState>, req: HttpRequest) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    Ok(HttpResponse::Ok().json(state.settings.playback().await))
+}
+
+/// Change some playback settings, persist them and apply them to MPD right away
+#[patch("/api/admin/settings")]
+pub async fn update_settings(
+    state: web::Data<AppState>,
+    body: web::Json<PlaybackSettingsUpdate>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    if let Err(response) = require_admin(&req) {
+        return Ok(response);
+    }
+    
+    let settings = match state.settings.update(body.into_inner()).await {
+        Ok(settings) => settings,
+        Err(SettingsError::Invalid(e)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e.to_string() })));
+        }
+    };
+    
+    // Saved settings are reapplied on the next reconnect even if MPD is unreachable now
+    let applied = settings.apply(&*state.mpd_client.lock().await).await;
+    if let Err(e) = applied {
+        error!("{}", e);
+        return Ok(HttpResponse::BadGateway().json(serde_json::json!({
+            "error": format!("Settings were saved but MPD did not accept them: {}", e),
+            "settings": settings
+        })));
+    }
+    
+    info!("Playback settings changed: {:?}", settings);
+    Ok(HttpResponse::Ok().json(settings))
+}
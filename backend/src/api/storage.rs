@@ -0,0 +1,8 @@
+use actix_web::{get, web, HttpResponse, Result};
+
+use crate::state::AppState;
+
+#[get("/api/storage")]
+pub async fn get_storage(state: web::Data<AppState>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.storage.info().await))
+}
@@ -2,7 +2,7 @@ use actix_web::{get, web, HttpRequest, HttpResponse, Result};
 use actix_web::web::Bytes;
 use actix_ws::Message;
 use futures::StreamExt;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use uuid::Uuid;
 use tokio::time::{interval, Duration};
 use tokio_stream::wrappers::IntervalStream;
@@ -11,7 +11,9 @@ use std::task::{Context, Poll};
 use std::sync::Arc;
 
 use crate::state::{AppState, SessionWrapper, IpConnectionTracker};
-use crate::mpd_manager::get_queue;
+use crate::direct_play::{direct_play_command, direct_play_message};
+use crate::models::ClientMessage;
+use crate::mpd_manager::{get_current_track, get_queue};
 
 /// Extract client IP from request, checking X-Forwarded-For header first (for proxied requests)
 fn get_client_ip(req: &HttpRequest) -> String {
@@ -83,6 +85,58 @@ impl<S> Drop for TrackedStream<S> {
     }
 }
 
+/// Current server time in milliseconds since the epoch, with sub-millisecond precision
+fn epoch_millis() -> f64 {
+    chrono::Utc::now().timestamp_micros() as f64 / 1000.0
+}
+
+/// Answer a client message; unknown or malformed messages are ignored
+async fn handle_client_message(state: &AppState, session_id: Uuid, text: &str, received_at: f64) -> Option<String> {
+    match serde_json::from_str::<ClientMessage>(text) {
+        // The client estimates its clock offset as ((receive - client) + (send - reply arrival)) / 2
+        Ok(ClientMessage::TimeSync { client_time }) => Some(serde_json::json!({
+            "type": "time_sync",
+            "data": {
+                "client_time": client_time,
+                "server_receive_time": received_at,
+                "server_send_time": epoch_millis()
+            }
+        }).to_string()),
+        // Newly opted-in clients get the current command right away instead of at the next change
+        Ok(ClientMessage::DirectPlay { enabled }) => {
+            state.set_direct_play(session_id, enabled).await;
+            info!("Direct play {} for session {}", if enabled { "enabled" } else { "disabled" }, session_id);
+            if !enabled {
+                return None;
+            }
+            match get_current_track(state).await {
+                Ok(current) => Some(direct_play_message(&direct_play_command(state, &current).await)),
+                Err(e) => {
+                    error!("Failed to get current track: {}", e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            debug!("Ignoring WebSocket message: {}", e);
+            None
+        }
+    }
+}
+
+/// The connecting user from `X-Username`, or the `username` query parameter since
+/// browsers can't set headers on WebSocket requests
+fn session_username(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get("X-Username").and_then(|h| h.to_str().ok()).map(str::to_string);
+    header
+        .or_else(|| {
+            web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.get("username").cloned())
+        })
+        .filter(|name| !name.is_empty())
+}
+
 #[get("/api/ws")]
 pub async fn websocket(
     req: HttpRequest,
@@ -109,6 +163,8 @@ pub async fn websocket(
         sessions.push(SessionWrapper {
             id: session_id,
             session: session.clone(),
+            direct_play: false,
+            username: session_username(&req),
         });
     }
     
@@ -125,12 +181,18 @@ pub async fn websocket(
                 Some(msg) = msg_stream.next() => {
                     match msg {
                         Ok(Message::Ping(bytes)) => {
-                            if session.pong(&bytes).await.is_err() {
+                            let pong = session.pong(&bytes).await;
+                            if pong.is_err() {
                                 break;
                             }
                         }
-                        Ok(Message::Text(_)) => {
-                            // Can handle client messages here if needed
+                        Ok(Message::Text(text)) => {
+                            let received_at = epoch_millis();
+                            if let Some(reply) = handle_client_message(&state_clone, session_id, &text, received_at).await {
+                                if session.text(reply).await.is_err() {
+                                    break;
+                                }
+                            }
                         }
                         Ok(Message::Close(_)) => {
                             info!("WebSocket connection closed by client: {}", session_id);
@@ -234,7 +296,7 @@ pub async fn stream_proxy(
     let stream_port = match quality.as_str() {
         "low" => "8001",
         "high" => "8003",
-        "medium" | _ => "8002", // default to medium
+        _ => "8002", // default to medium
     };
     
     // Get MPD stream URL from environment or use default
@@ -0,0 +1,288 @@
+use actix_files::NamedFile;
+use actix_web::http::header;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Result};
+use log::error;
+use serde::Deserialize;
+
+use crate::analysis::load_waveform;
+use crate::api::upload::extract_username;
+use crate::library::{library_tracks, search_tracks, LibraryFilter, SortKey};
+use crate::lyrics::load_lyrics;
+use crate::models::TrackPage;
+use crate::state::AppState;
+use crate::stats::save_track_stats;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct ListTracksQuery {
+    /// 1-based page number
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    /// `added_at` (default), `artist`, `title` or `plays`
+    pub sort: Option<String>,
+    /// `asc` or `desc`; defaults to newest/most played first and A-Z for names
+    pub order: Option<String>,
+    pub uploader: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaveformQuery {
+    /// `json` (default) or `binary`
+    pub format: Option<String>,
+}
+
+/// Browse the catalog a page at a time
+#[get("/api/tracks")]
+pub async fn list_tracks(
+    state: web::Data<AppState>,
+    query: web::Query<ListTracksQuery>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    
+    let sort = match query.sort.as_deref() {
+        None => SortKey::AddedAt,
+        Some(value) => match SortKey::parse(value) {
+            Some(sort) => sort,
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Invalid sort, expected added_at, artist, title or plays"
+                })));
+            }
+        },
+    };
+    let descending = match query.order.as_deref().map(|o| o.trim().to_lowercase()) {
+        None => sort.descending_by_default(),
+        Some(order) if order == "asc" => false,
+        Some(order) if order == "desc" => true,
+        Some(_) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid order, expected asc or desc"
+            })));
+        }
+    };
+    
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let filter = LibraryFilter {
+        uploader: query.uploader,
+        artist: query.artist,
+        album: query.album,
+        genre: query.genre,
+    };
+    
+    let tracks = library_tracks(&state, &filter, sort, descending).await;
+    let total = tracks.len();
+    let tracks = tracks
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    
+    Ok(HttpResponse::Ok().json(TrackPage {
+        tracks,
+        total,
+        page,
+        per_page,
+    }))
+}
+
+/// Full-text search over titles, artists, albums, genres and uploaders
+#[get("/api/search")]
+pub async fn search(
+    state: web::Data<AppState>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse> {
+    if query.q.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Search query is empty"
+        })));
+    }
+    
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut tracks = search_tracks(&state, &query.q).await;
+    let total = tracks.len();
+    tracks.truncate(limit);
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "query": query.q,
+        "total": total,
+        "tracks": tracks
+    })))
+}
+
+/// Add or remove the caller's like; liking twice has no further effect
+async fn set_like(state: &AppState, req: &HttpRequest, track_id: String, liked: bool) -> HttpResponse {
+    if state.storage.find_by_track_id(&track_id).await.is_none() {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Track not found"
+        }));
+    }
+    
+    let username = extract_username(req);
+    let (changed, likes) = {
+        let mut stats = state.track_stats.write().await;
+        let track_stats = stats.entry(track_id).or_default();
+        let changed = if liked {
+            track_stats.liked_by.insert(username)
+        } else {
+            track_stats.liked_by.remove(&username)
+        };
+        (changed, track_stats.likes())
+    };
+    if changed {
+        save_track_stats(state).await;
+    }
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "liked": liked,
+        "likes": likes
+    }))
+}
+
+#[post("/api/tracks/{id}/like")]
+pub async fn like_track(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    Ok(set_like(&state, &req, path.into_inner(), true).await)
+}
+
+#[delete("/api/tracks/{id}/like")]
+pub async fn unlike_track(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    Ok(set_like(&state, &req, path.into_inner(), false).await)
+}
+
+/// Waveform peaks of a track, one byte (0-255) per point spread evenly over its duration
+/// `?format=binary` returns the raw bytes instead of JSON
+#[get("/api/tracks/{id}/waveform")]
+pub async fn get_waveform(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<WaveformQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let track_id = path.into_inner();
+    
+    let (duration, analysis_version, waveform_version) = match state.tracks_metadata.read().await.get(&track_id) {
+        Some(track) => (track.duration, track.analysis_version, track.waveform_version),
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Track not found"
+            })));
+        }
+    };
+    
+    // Waveforms are rebuilt for the same track id whenever the track is re-analyzed,
+    // so caches revalidate by an ETag that follows the versions it was built with
+    let binary = query.format.as_deref() == Some("binary");
+    let etag = format!("\"{}-{}-{}\"", analysis_version, waveform_version, if binary { "bin" } else { "json" });
+    let cached = req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if cached {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .finish());
+    }
+    
+    let peaks = match load_waveform(&track_id).await {
+        Ok(peaks) => peaks,
+        Err(_) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Waveform not computed yet"
+            })));
+        }
+    };
+    
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header((header::ETAG, etag));
+    if binary {
+        return Ok(response
+            .content_type("application/octet-stream")
+            .body(peaks));
+    }
+    
+    Ok(response.json(serde_json::json!({
+        "track_id": track_id,
+        "duration": duration,
+        "peaks": peaks
+    })))
+}
+
+/// The original uploaded file, with Range requests for seeking and resumable downloads
+#[get("/api/tracks/{id}/file")]
+pub async fn get_track_file(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let Some(file) = state.storage.find_by_track_id(&path).await else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Track not found"
+        })));
+    };
+    
+    let named = match NamedFile::open_async(state.storage.path(&file.filename)).await {
+        Ok(named) => named,
+        Err(e) => {
+            error!("Failed to open {}: {}", file.filename, e);
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Track file not found"
+            })));
+        }
+    };
+    
+    // Analysis may retag the file while keeping its modification time, so caches
+    // revalidate by ETag (which follows the file's inode and size) only
+    let mut response = named.use_last_modified(false).into_response(&req);
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-cache"),
+    );
+    Ok(response)
+}
+
+/// Lyrics of a track; synced lyrics list their lines with timestamps in seconds
+#[get("/api/tracks/{id}/lyrics")]
+pub async fn get_lyrics(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let track_id = path.into_inner();
+    
+    if !state.tracks_metadata.read().await.contains_key(&track_id) {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Track not found"
+        })));
+    }
+    
+    match load_lyrics(&track_id).await {
+        Some(lyrics) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "track_id": track_id,
+            "lyrics": lyrics
+        }))),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No lyrics for this track"
+        }))),
+    }
+}
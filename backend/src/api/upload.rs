@@ -1,139 +1,450 @@
-use actix_multipart::Multipart;
-use actix_web::{post, web, HttpRequest, HttpResponse, Result};
+use actix_multipart::{Field, Multipart};
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Result};
 use futures::{StreamExt, TryStreamExt};
 use log::{error, info, warn};
-use std::io::Write;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
-use crate::models::{Track, UploadResponse};
-use crate::mpd_manager::{add_file_to_mpd, remove_last_track_from_queue};
+use crate::audio::{read_tags, AudioTags};
+use crate::catalog::save_catalog;
+use crate::dedup::{find_duplicate, find_similar};
+use crate::eviction::{free_up_space, reserve_space};
+use crate::jobs::{fail_job, report_progress, set_job_state, start_job, update_job, PROGRESS_STEP_BYTES};
+use crate::lyrics::{attach_lyrics, parse_lrc, MAX_LRC_SIZE};
+use crate::models::{BatchUploadItem, BatchUploadResponse, QuotaStatus, SkippedFile, Track, UploadJobState, UploadResponse};
+use crate::mpd_manager::{add_file_to_mpd, escape_username};
+use crate::quota::{check_upload_allowed, get_quota_status, QuotaExceeded};
 use crate::state::AppState;
+use crate::storage::Reservation;
 
-const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; // 100 MB
-const DEFAULT_MAX_TOTAL_STORAGE: u64 = 300 * 1024 * 1024; // 300 MB default total storage limit
+pub const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; // 100 MB
+const SUPPORTED_EXTENSIONS: [&str; 5] = ["mp3", "flac", "ogg", "m4a", "wav"];
 
-/// Get the maximum total storage size from environment variable or use default
-/// Environment variable: MAX_TOTAL_STORAGE (in bytes, or with suffix like "500MB", "1GB")
-pub fn get_max_total_storage() -> u64 {
-    match std::env::var("MAX_TOTAL_STORAGE") {
-        Ok(val) => {
-            // Try to parse as number with optional suffix
-            let val = val.trim().to_uppercase();
-            if let Some(mb_pos) = val.find("MB") {
-                if let Ok(num) = val[..mb_pos].trim().parse::<u64>() {
-                    return num * 1024 * 1024;
-                }
-            } else if let Some(gb_pos) = val.find("GB") {
-                if let Ok(num) = val[..gb_pos].trim().parse::<u64>() {
-                    return num * 1024 * 1024 * 1024;
-                }
-            } else if let Ok(num) = val.parse::<u64>() {
-                return num;
-            }
-            warn!("Invalid MAX_TOTAL_STORAGE format: '{}', using default", val);
-            DEFAULT_MAX_TOTAL_STORAGE
+/// Why an upload, or one file of a batch, was rejected
+#[derive(Debug)]
+pub struct UploadError {
+    status: StatusCode,
+    message: String,
+    quota: Option<Box<(QuotaExceeded, QuotaStatus)>>,
+}
+
+impl UploadError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            quota: None,
+        }
+    }
+    
+    fn quota(exceeded: QuotaExceeded, quota: QuotaStatus) -> Self {
+        let status = match exceeded {
+            QuotaExceeded::Storage => StatusCode::INSUFFICIENT_STORAGE,
+            QuotaExceeded::QueuedTracks | QuotaExceeded::UploadsPerHour => StatusCode::TOO_MANY_REQUESTS,
+        };
+        Self {
+            status,
+            message: exceeded.message().to_string(),
+            quota: Some(Box::new((exceeded, quota))),
+        }
+    }
+    
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    
+    pub fn into_response(self) -> HttpResponse {
+        match self.quota.map(|quota| *quota) {
+            Some((exceeded, quota)) => quota_exceeded_response(exceeded, &quota),
+            None => HttpResponse::build(self.status).json(serde_json::json!({
+                "error": self.message
+            })),
         }
-        Err(_) => DEFAULT_MAX_TOTAL_STORAGE,
     }
 }
 
-/// Calculate the total size of all files in the uploads directory
-pub fn get_uploads_directory_size() -> std::io::Result<u64> {
-    let uploads_dir = PathBuf::from("uploads");
-    let mut total_size = 0u64;
+/// Reject files we can't play before reading them
+pub fn check_file_type(filename: &str) -> std::result::Result<(), UploadError> {
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
     
-    if uploads_dir.exists() && uploads_dir.is_dir() {
-        for entry in std::fs::read_dir(uploads_dir)? {
-            let entry = entry?;
-            let metadata = entry.metadata()?;
-            if metadata.is_file() {
-                total_size += metadata.len();
-            }
+    if !SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+        return Err(UploadError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid file type. Supported formats: mp3, flac, ogg, m4a, wav",
+        ));
+    }
+    Ok(())
+}
+
+/// `.lrc` lyrics sent alongside audio files
+fn is_lrc(filename: &str) -> bool {
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("lrc"))
+}
+
+/// File name without its extension, for pairing `.lrc` files with audio
+fn file_stem(filename: &str) -> Option<String> {
+    std::path::Path::new(filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_lowercase)
+}
+
+/// Read a small text field such as an `.lrc` file into memory
+async fn read_text_field(field: &mut Field, max_size: usize) -> std::result::Result<String, String> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| format!("Error reading file: {}", e))?;
+        if data.len() + chunk.len() > max_size {
+            while let Some(Ok(_)) = field.next().await {}
+            return Err(format!("File too large (max {} KB)", max_size / 1024));
         }
+        data.extend_from_slice(&chunk);
     }
-    
-    Ok(total_size)
+    // Tolerate a UTF-8 byte order mark and stray invalid bytes
+    let text = String::from_utf8_lossy(&data);
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Check per-user quotas against an upload of `expected_size` bytes
+async fn check_quota(
+    state: &AppState,
+    username: &str,
+    expected_size: Option<u64>,
+) -> std::result::Result<QuotaStatus, UploadError> {
+    let quota = get_quota_status(state, username).await;
+    if let Err(exceeded) = check_upload_allowed(&quota) {
+        warn!("Upload from {} rejected: {}", username, exceeded.message());
+        return Err(UploadError::quota(exceeded, quota));
+    }
+    if expected_size.is_some_and(|size| size > quota.storage_bytes.remaining) {
+        warn!("Upload from {} exceeds personal storage quota", username);
+        return Err(UploadError::quota(QuotaExceeded::Storage, quota));
+    }
+    Ok(quota)
+}
+
+/// Make room in shared storage for `size` bytes, evicting other uploads if needed
+async fn make_room(state: &AppState, size: u64) -> std::result::Result<(), UploadError> {
+    let max_storage = state.storage.max_total();
+    match free_up_space(state, size).await {
+        Ok(true) => {
+            let current_size = state.storage.total_size().await;
+            info!("Storage check passed. Current size: {} MB / {} MB",
+                  current_size / 1024 / 1024,
+                  max_storage / 1024 / 1024);
+            Ok(())
+        }
+        Ok(false) => {
+            error!("Unable to free up enough space for upload");
+            Err(UploadError::new(
+                StatusCode::INSUFFICIENT_STORAGE,
+                format!("Storage limit reached ({}MB). Unable to free up space for new upload.", max_storage / 1024 / 1024),
+            ))
+        }
+        Err(e) => {
+            error!("Error checking storage: {}", e);
+            Err(UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, "Error checking storage availability"))
+        }
+    }
+}
+
+/// Set aside room in shared storage for a whole batch before any file is accepted
+async fn reserve_room(state: &AppState, size: u64) -> std::result::Result<Reservation, UploadError> {
+    reserve_space(state, size).await.ok_or_else(|| {
+        let max_storage = state.storage.max_total();
+        UploadError::new(
+            StatusCode::INSUFFICIENT_STORAGE,
+            format!("Storage limit reached ({}MB). Unable to free up space for this upload.", max_storage / 1024 / 1024),
+        )
+    })
+}
+
+/// Validate an upload before it is stored: file type, size and per-user quotas
+/// `staged` bytes of the user's other unfinished uploads count against their storage quota.
+/// Shared storage is only made room in once the upload is known not to be a duplicate
+pub async fn check_upload_quota(
+    state: &AppState,
+    username: &str,
+    filename: &str,
+    expected_size: Option<u64>,
+    staged: u64,
+) -> std::result::Result<QuotaStatus, UploadError> {
+    check_file_type(filename)?;
+    if expected_size.is_some_and(|size| size > MAX_FILE_SIZE as u64) {
+        return Err(UploadError::new(StatusCode::BAD_REQUEST, "File too large (max 100MB)"));
+    }
+    check_quota(state, username, expected_size.map(|size| size + staged)).await
+}
+
+/// Name an upload is stored under: {uuid}_{username}_{original_filename}
+/// Underscores in the username are escaped so it can be read back unambiguously
+pub fn stored_filename(track_id: &str, username: &str, filename: &str) -> String {
+    let sanitized_filename = sanitize_filename::sanitize(filename);
+    let sanitized_username = escape_username(&sanitize_filename::sanitize(username));
+    format!("{}_{}_{}", track_id, sanitized_username, sanitized_filename)
+}
+
+/// Take a fully written upload through deduplication, eviction, tag extraction, indexing and queueing
+/// The file must already be in the uploads directory under `final_filename`; the job ends
+/// up queued or failed
+pub async fn ingest_upload(
+    state: &AppState,
+    job_id: &str,
+    username: &str,
+    track_id: String,
+    original_filename: &str,
+    final_filename: String,
+    content_hash: String,
+) -> std::result::Result<UploadResponse, UploadError> {
+    let result = process_upload(state, job_id, username, track_id, original_filename, final_filename, content_hash).await;
+    match &result {
+        Ok(response) => {
+            update_job(state, job_id, |job| {
+                job.state = UploadJobState::Queued;
+                job.track_id = Some(response.track_id.clone());
+                job.deduplicated = response.deduplicated;
+            }).await;
+        }
+        Err(e) => fail_job(state, job_id, e.message()).await,
+    }
+    result
+}
+
+fn queue_error(e: impl std::fmt::Display) -> UploadError {
+    error!("Failed to add file to MPD: {}", e);
+    UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("File uploaded but failed to add to queue: {}", e))
 }
 
-/// Get the oldest file in the uploads directory (by modification time)
-fn get_oldest_file() -> std::io::Result<Option<PathBuf>> {
-    let uploads_dir = PathBuf::from("uploads");
-    let mut oldest_file: Option<(PathBuf, std::time::SystemTime)> = None;
+async fn process_upload(
+    state: &AppState,
+    job_id: &str,
+    username: &str,
+    track_id: String,
+    original_filename: &str,
+    final_filename: String,
+    content_hash: String,
+) -> std::result::Result<UploadResponse, UploadError> {
+    set_job_state(state, job_id, UploadJobState::Validating).await;
     
-    if uploads_dir.exists() && uploads_dir.is_dir() {
-        for entry in std::fs::read_dir(uploads_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            // Skip .gitkeep and other hidden files
-            if let Some(filename) = path.file_name() {
-                if filename.to_string_lossy().starts_with('.') {
-                    continue;
-                }
-            }
-            
-            let metadata = entry.metadata()?;
-            if metadata.is_file() {
-                let modified = metadata.modified()?;
-                
-                match &oldest_file {
-                    None => oldest_file = Some((path, modified)),
-                    Some((_, oldest_time)) => {
-                        if modified < *oldest_time {
-                            oldest_file = Some((path, modified));
-                        }
-                    }
-                }
-            }
+    // Reuse an existing copy of the same song instead of storing it again
+    if let Some(existing) = find_duplicate(state, &final_filename, &content_hash).await {
+        if let Err(e) = state.storage.remove(&final_filename).await {
+            warn!("Failed to remove duplicate upload {}: {}", final_filename, e);
         }
+        info!("Duplicate upload {}, queueing existing {} for {}", final_filename, existing.filename, username);
+        
+        set_job_state(state, job_id, UploadJobState::Indexing).await;
+        add_file_to_mpd(state, &existing.filename, username)
+            .await
+            .map_err(queue_error)?;
+        
+        let queue_update = serde_json::json!({
+            "type": "queue_update",
+            "data": {}
+        });
+        state.broadcast_message(&queue_update.to_string()).await;
+        
+        return Ok(UploadResponse {
+            success: true,
+            track_id: existing.track_id,
+            filename: existing.filename,
+            deduplicated: true,
+            possible_duplicate_of: None,
+            job_id: job_id.to_string(),
+            skipped_lyrics: Vec::new(),
+        });
     }
     
-    Ok(oldest_file.map(|(path, _)| path))
+    // Only a new song needs room, so nothing is evicted for a duplicate
+    let size = tokio::fs::metadata(state.storage.path(&final_filename)).await.map(|m| m.len()).unwrap_or(0);
+    if let Err(e) = make_room(state, size).await {
+        let _ = state.storage.remove(&final_filename).await;
+        return Err(e);
+    }
+    // Only a kept file uses one of the uploader's hourly slots
+    state.quota.record_upload(username).await;
+    
+    if let Err(e) = state.storage.record_write(&final_filename, Some(content_hash.clone())).await {
+        warn!("Failed to index new upload {}: {}", final_filename, e);
+    }
+    let possible_duplicate_of = find_similar(state, &final_filename).await.map(|similar| similar.track_id);
+    
+    set_job_state(state, job_id, UploadJobState::ExtractingTags).await;
+    let path = state.storage.path(&final_filename);
+    let tags = match tokio::task::spawn_blocking(move || read_tags(&path)).await {
+        Ok(Ok(tags)) => tags,
+        Ok(Err(e)) => {
+            warn!("Failed to read tags of {}: {}", final_filename, e);
+            AudioTags::default()
+        }
+        Err(e) => {
+            warn!("Tag reader for {} failed: {}", final_filename, e);
+            AudioTags::default()
+        }
+    };
+    // Store metadata
+    let track = Track {
+        id: track_id.clone(),
+        filename: final_filename.clone(),
+        title: tags.title.or_else(|| Some(sanitize_filename::sanitize(original_filename))),
+        artist: tags.artist,
+        album: tags.album,
+        genre: tags.genre,
+        duration: tags.duration,
+        added_by: username.to_string(),
+        added_at: chrono::Utc::now(),
+        loudness: None,
+        cue_in: None,
+        cue_out: None,
+        analysis_version: 0,
+        waveform_version: 0,
+        upload_hash: Some(content_hash),
+        // Embedded lyrics are extracted during analysis
+        lyrics: None,
+        possible_duplicate_of: possible_duplicate_of.clone(),
+    };
+    
+    state.search_index.insert(&track).await;
+    {
+        let mut metadata = state.tracks_metadata.write().await;
+        metadata.insert(track_id.clone(), track);
+    }
+    save_catalog(state).await;
+    
+    // Add to MPD queue
+    set_job_state(state, job_id, UploadJobState::Indexing).await;
+    add_file_to_mpd(state, &final_filename, username)
+        .await
+        .map_err(queue_error)?;
+    
+    // Cue points found by the analysis are applied to the queue entry once it finishes
+    state.analysis_queue.push(&final_filename).await;
+    
+    // Notify via WebSocket
+    let queue_update = serde_json::json!({
+        "type": "queue_update",
+        "data": {}
+    });
+    state.broadcast_message(&queue_update.to_string()).await;
+    
+    Ok(UploadResponse {
+        success: true,
+        track_id,
+        filename: final_filename,
+        deduplicated: false,
+        possible_duplicate_of,
+        job_id: job_id.to_string(),
+        skipped_lyrics: Vec::new(),
+    })
+}
+
+/// A multipart file written to the uploads directory, not yet ingested
+struct ReceivedFile {
+    track_id: String,
+    filename: String,
+    content_hash: String,
+    size: u64,
 }
 
-/// Try to free up space by deleting oldest files until there's enough room
-fn free_up_space(needed_size: usize) -> std::io::Result<bool> {
-    let max_storage = get_max_total_storage();
-    let mut freed = 0u64;
-    let current_size = get_uploads_directory_size()?;
-    let needed_total = needed_size as u64;
+/// Stream one multipart file field into the uploads directory
+async fn receive_file(
+    state: &AppState,
+    username: &str,
+    field: &mut Field,
+    job_id: &str,
+    filename: &str,
+    quota: &QuotaStatus,
+    storage_remaining: u64,
+) -> std::result::Result<ReceivedFile, UploadError> {
+    // Generate unique ID and sanitize filename
+    let track_id = Uuid::new_v4().to_string();
+    let final_filename = stored_filename(&track_id, username, filename);
+    let filepath = state.storage.path(&final_filename);
     
-    // If already enough space, no need to delete
-    if current_size + needed_total <= max_storage {
-        return Ok(true);
-    }
+    info!("Uploading file: {} as {}", filename, final_filename);
     
-    let to_free = (current_size + needed_total) - max_storage;
+    // Create file
+    state.storage.start_write(&final_filename).await;
+    let mut file = match tokio::fs::File::create(&filepath).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to create file: {}", e);
+            let _ = state.storage.remove(&final_filename).await;
+            return Err(UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save file"));
+        }
+    };
     
-    info!("Need to free up {} bytes to accommodate new upload", to_free);
+    let mut total_size = 0usize;
+    let mut reported_size = 0u64;
+    let mut hasher = Sha256::new();
     
-    // Keep deleting oldest files until we have enough space
-    while freed < to_free {
-        match get_oldest_file()? {
-            Some(path) => {
-                let file_size = std::fs::metadata(&path)?.len();
-                
-                info!("Deleting old file to free space: {:?} ({} bytes)", path, file_size);
-                
-                if let Err(e) = std::fs::remove_file(&path) {
-                    error!("Failed to delete file {:?}: {}", path, e);
-                    return Ok(false);
-                }
-                
-                freed += file_size;
-            }
-            None => {
-                warn!("No more files to delete but still need {} bytes", to_free - freed);
-                return Ok(false);
+    // Read and write file chunks, hashing them on the way for deduplication
+    while let Some(chunk) = field.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Error reading chunk: {}", e);
+                let _ = state.storage.remove(&final_filename).await;
+                return Err(UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, "Error reading file"));
             }
+        };
+        
+        total_size += data.len();
+        if total_size > MAX_FILE_SIZE {
+            let _ = state.storage.remove(&final_filename).await;
+            return Err(UploadError::new(StatusCode::BAD_REQUEST, "File too large (max 100MB)"));
+        }
+        
+        if total_size as u64 > storage_remaining {
+            let _ = state.storage.remove(&final_filename).await;
+            warn!("Upload from {} exceeds personal storage quota", username);
+            return Err(UploadError::quota(QuotaExceeded::Storage, quota.clone()));
+        }
+        
+        hasher.update(&data);
+        if let Err(e) = file.write_all(&data).await {
+            error!("Error writing file: {}", e);
+            let _ = state.storage.remove(&final_filename).await;
+            return Err(UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, "Error saving file"));
+        }
+        
+        if total_size as u64 - reported_size >= PROGRESS_STEP_BYTES {
+            reported_size = total_size as u64;
+            report_progress(state, job_id, reported_size).await;
         }
     }
     
-    info!("Successfully freed {} bytes", freed);
-    Ok(true)
+    if let Err(e) = file.flush().await {
+        error!("Error writing file: {}", e);
+        let _ = state.storage.remove(&final_filename).await;
+        return Err(UploadError::new(StatusCode::INTERNAL_SERVER_ERROR, "Error saving file"));
+    }
+    
+    info!("File saved successfully: {}", final_filename);
+    update_job(state, job_id, |job| job.bytes_received = total_size as u64).await;
+    Ok(ReceivedFile {
+        track_id,
+        filename: final_filename,
+        content_hash: format!("{:x}", hasher.finalize()),
+        size: total_size as u64,
+    })
 }
 
+/// Upload one or more files in a single multipart request
+///
+/// The per-user storage quota and room in shared storage for the whole request are checked
+/// before reading any file, and files are queued in the order they were submitted. A
+/// single file gets the plain `UploadResponse` (or error); several files get per-file
+/// results. `.lrc` files are attached as lyrics to the audio file with the same name, or
+/// to the only audio file.
 #[post("/api/upload")]
 pub async fn upload_music(
     mut payload: Multipart,
@@ -142,165 +453,213 @@ pub async fn upload_music(
 ) -> Result<HttpResponse> {
     let username = extract_username(&req);
     
+    // The request body bounds the size of the whole batch
+    let batch_size = req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let quota = match check_quota(&state, &username, batch_size).await {
+        Ok(quota) => quota,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let mut storage_remaining = quota.storage_bytes.remaining;
+    // Shared storage for the whole batch is checked and set aside up front, so a batch
+    // that can't fit is rejected before anything is evicted for its first files
+    let mut reservation = match batch_size {
+        Some(size) => match reserve_room(&state, size).await {
+            Ok(reservation) => Some(reservation),
+            Err(e) => return Ok(e.into_response()),
+        },
+        None => None,
+    };
+    
+    let mut results: Vec<(String, String, std::result::Result<UploadResponse, UploadError>)> = Vec::new();
+    let mut lrc_files: Vec<(String, String)> = Vec::new();
+    let mut skipped_lyrics: Vec<SkippedFile> = Vec::new();
+    
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition();
-        
-        if let Some(filename) = content_disposition.and_then(|cd| cd.get_filename()) {
-            // Validate file extension
-            let extension = std::path::Path::new(filename)
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("");
-            
-            if !["mp3", "flac", "ogg", "m4a", "wav"].contains(&extension.to_lowercase().as_str()) {
-                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Invalid file type. Supported formats: mp3, flac, ogg, m4a, wav"
-                })));
+        let filename = match field.content_disposition().and_then(|cd| cd.get_filename()) {
+            Some(filename) => filename.to_string(),
+            None => {
+                // Not a file; skip its contents
+                while let Some(Ok(_)) = field.next().await {}
+                continue;
             }
-            
-            // Check storage limit and free up space if needed
-            let max_storage = get_max_total_storage();
-            let current_size = get_uploads_directory_size().unwrap_or(0);
-            
-            // Check if we need to remove the last track from queue
-            // This happens when storage is at or near maximum
-            if current_size >= max_storage {
-                info!("Storage at maximum ({} / {} bytes), removing last track from queue and deleting file", current_size, max_storage);
-                if let Err(e) = remove_last_track_from_queue(&state, true).await {
-                    warn!("Failed to remove last track from queue: {}", e);
+        };
+        
+        if is_lrc(&filename) {
+            match read_text_field(&mut field, MAX_LRC_SIZE).await {
+                Ok(text) => lrc_files.push((filename, text)),
+                Err(error) => {
+                    warn!("Skipped lyrics file {} from {}: {}", filename, username, error);
+                    skipped_lyrics.push(SkippedFile { filename, error });
                 }
             }
+            continue;
+        }
+        
+        let job_id = start_job(&state, None, &username, &filename, None).await;
+        let received = async {
+            check_file_type(&filename)?;
             
-            match free_up_space(MAX_FILE_SIZE) {
-                Ok(true) => {
-                    let current_size = get_uploads_directory_size().unwrap_or(0);
-                    info!("Storage check passed. Current size: {} MB / {} MB", 
-                          current_size / 1024 / 1024, 
-                          max_storage / 1024 / 1024);
-                }
-                Ok(false) => {
-                    // Try removing last track from queue (and deleting file) as a last resort
-                    info!("Unable to free up enough space, removing last track from queue and deleting file");
-                    if let Err(e) = remove_last_track_from_queue(&state, true).await {
-                        warn!("Failed to remove last track from queue: {}", e);
-                    }
-                    
-                    // Try freeing space again after removing from queue
-                    match free_up_space(MAX_FILE_SIZE) {
-                        Ok(true) => {
-                            info!("Successfully freed space after removing track from queue");
-                        }
-                        Ok(false) | Err(_) => {
-                            error!("Unable to free up enough space for upload even after removing track from queue");
-                            return Ok(HttpResponse::InsufficientStorage().json(serde_json::json!({
-                                "error": format!("Storage limit reached ({}MB). Unable to free up space for new upload.", max_storage / 1024 / 1024)
-                            })));
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Error checking storage: {}", e);
-                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Error checking storage availability"
-                    })));
-                }
+            // Queue and hourly limits can run out partway through a batch
+            let quota = get_quota_status(&state, &username).await;
+            if let Err(exceeded) = check_upload_allowed(&quota) {
+                warn!("Upload from {} rejected: {}", username, exceeded.message());
+                return Err(UploadError::quota(exceeded, quota));
             }
             
-            // Generate unique ID and sanitize filename
-            let track_id = Uuid::new_v4().to_string();
-            let sanitized_filename = sanitize_filename::sanitize(filename);
-            let sanitized_username = sanitize_filename::sanitize(&username);
-            let final_filename = format!("{}_{}_{}", track_id, sanitized_username, sanitized_filename);
-            let filepath = PathBuf::from("uploads").join(&final_filename);
-            
-            info!("Uploading file: {} as {}", filename, final_filename);
-            
-            // Create file
-            let mut file = match std::fs::File::create(&filepath) {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("Failed to create file: {}", e);
-                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Failed to save file"
-                    })));
-                }
-            };
-            
-            let mut total_size = 0usize;
-            
-            // Read and write file chunks
-            while let Some(chunk) = field.next().await {
-                let data = chunk.map_err(|e| {
-                    error!("Error reading chunk: {}", e);
-                    actix_web::error::ErrorInternalServerError("Error reading file")
-                })?;
-                
-                total_size += data.len();
-                if total_size > MAX_FILE_SIZE {
-                    let _ = std::fs::remove_file(&filepath);
-                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "File too large (max 100MB)"
-                    })));
+            receive_file(&state, &username, &mut field, &job_id, &filename, &quota, storage_remaining).await
+        }.await;
+        
+        let result = match received {
+            Ok(file) => {
+                // The file now makes its own room during ingest
+                if let Some(reservation) = reservation.as_mut() {
+                    reservation.release(file.size);
                 }
-                
-                file.write_all(&data).map_err(|e| {
-                    error!("Error writing file: {}", e);
-                    actix_web::error::ErrorInternalServerError("Error saving file")
-                })?;
+                ingest_upload(&state, &job_id, &username, file.track_id, &filename, file.filename, file.content_hash)
+                    .await
+                    .inspect(|response| {
+                        if !response.deduplicated {
+                            storage_remaining = storage_remaining.saturating_sub(file.size);
+                        }
+                    })
             }
-            
-            info!("File saved successfully: {}", final_filename);
-            
-            // Store metadata
-            let track = Track {
-                id: track_id.clone(),
-                filename: final_filename.clone(),
-                title: Some(sanitized_filename.clone()),
-                artist: None,
-                album: None,
-                duration: None,
-                added_by: username.clone(),
-                added_at: chrono::Utc::now(),
-            };
-            
-            {
-                let mut metadata = state.tracks_metadata.write().await;
-                metadata.insert(track_id.clone(), track);
+            Err(e) => {
+                fail_job(&state, &job_id, e.message()).await;
+                // Rejected files still have to be read past to get to the next one
+                while let Some(Ok(_)) = field.next().await {}
+                Err(e)
             }
-            
-            // Add to MPD queue
-            if let Err(e) = add_file_to_mpd(&state, &final_filename).await {
-                error!("Failed to add file to MPD: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("File uploaded but failed to add to queue: {}", e)
-                })));
+        };
+        
+        results.push((filename, job_id, result));
+    }
+    
+    for (lrc_name, text) in lrc_files {
+        let stem = file_stem(&lrc_name);
+        let track_id = results.iter()
+            .filter_map(|(filename, _, result)| Some((filename, result.as_ref().ok()?)))
+            .find(|(filename, _)| results.len() == 1 || file_stem(filename) == stem)
+            .map(|(_, response)| response.track_id.clone());
+        let attached = match (track_id, parse_lrc(&text)) {
+            (None, _) => Err("No uploaded track with the same name".to_string()),
+            (_, None) => Err("Lyrics file is empty".to_string()),
+            (Some(track_id), Some(lyrics)) => attach_lyrics(&state, &track_id, &lyrics).await,
+        };
+        if let Err(error) = attached {
+            warn!("Skipped lyrics file {} from {}: {}", lrc_name, username, error);
+            skipped_lyrics.push(SkippedFile { filename: lrc_name, error });
+        }
+    }
+    
+    if results.len() <= 1 {
+        return Ok(match results.pop() {
+            Some((_, _, Ok(mut response))) => {
+                response.skipped_lyrics = skipped_lyrics;
+                HttpResponse::Ok().json(response)
             }
-            
-            // Notify via WebSocket
-            let queue_update = serde_json::json!({
-                "type": "queue_update",
-                "data": {}
-            });
-            state.broadcast_message(&queue_update.to_string()).await;
-            
-            return Ok(HttpResponse::Ok().json(UploadResponse {
+            Some((_, _, Err(e))) => e.into_response(),
+            None if !skipped_lyrics.is_empty() => HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Lyrics files need an audio file in the same upload",
+                "skipped_lyrics": skipped_lyrics
+            })),
+            None => HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "No file provided"
+            })),
+        });
+    }
+    
+    let results: Vec<BatchUploadItem> = results
+        .into_iter()
+        .map(|(filename, job_id, result)| match result {
+            Ok(response) => BatchUploadItem {
+                filename,
+                job_id,
                 success: true,
-                track_id,
-                filename: final_filename,
-            }));
-        }
+                track_id: Some(response.track_id),
+                stored_filename: Some(response.filename),
+                deduplicated: response.deduplicated,
+                error: None,
+            },
+            Err(e) => BatchUploadItem {
+                filename,
+                job_id,
+                success: false,
+                track_id: None,
+                stored_filename: None,
+                deduplicated: false,
+                error: Some(e.message().to_string()),
+            },
+        })
+        .collect();
+    let uploaded = results.iter().filter(|r| r.success).count();
+    
+    info!("Batch upload from {}: {} of {} files uploaded", username, uploaded, results.len());
+    
+    Ok(HttpResponse::Ok().json(BatchUploadResponse {
+        success: uploaded == results.len(),
+        uploaded,
+        failed: results.len() - uploaded,
+        results,
+        skipped_lyrics,
+    }))
+}
+
+/// State of an upload job, for clients that poll instead of listening for `upload_progress`
+#[get("/api/uploads/{job_id}")]
+pub async fn get_upload_job(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    match state.upload_jobs.get(&path).await {
+        Some(job) if job.username != extract_username(&req) => Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Upload job belongs to another user"
+        }))),
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Upload job not found"
+        }))),
     }
+}
+
+/// The caller's recent upload jobs, newest first
+#[get("/api/uploads")]
+pub async fn list_upload_jobs(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let username = extract_username(&req);
+    Ok(HttpResponse::Ok().json(state.upload_jobs.for_user(&username).await))
+}
+
+/// 429 for queue/rate limits, 507 for storage, with the remaining allowance in the body
+pub fn quota_exceeded_response(exceeded: QuotaExceeded, quota: &QuotaStatus) -> HttpResponse {
+    let body = serde_json::json!({
+        "error": exceeded.message(),
+        "quota": quota
+    });
     
-    Ok(HttpResponse::BadRequest().json(serde_json::json!({
-        "error": "No file provided"
-    })))
+    match exceeded {
+        QuotaExceeded::Storage => HttpResponse::InsufficientStorage().json(body),
+        QuotaExceeded::QueuedTracks => HttpResponse::TooManyRequests().json(body),
+        QuotaExceeded::UploadsPerHour => {
+            let mut builder = HttpResponse::TooManyRequests();
+            if let Some(next_slot) = quota.next_upload_slot_at {
+                let retry_after = (next_slot - chrono::Utc::now()).num_seconds().max(1);
+                builder.insert_header(("Retry-After", retry_after.to_string()));
+            }
+            builder.json(body)
+        }
+    }
 }
 
-fn extract_username(req: &HttpRequest) -> String {
+pub fn extract_username(req: &HttpRequest) -> String {
     req.headers()
         .get("X-Username")
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
         .unwrap_or_else(|| "Anonymous".to_string())
 }
-
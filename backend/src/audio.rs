@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+/// Format of the decoded samples handed to `decode_file` callbacks
+#[derive(Debug, Clone, Copy)]
+pub struct AudioSpec {
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+/// Tags read from an uploaded file; anything missing is left to MPD or the filename
+#[derive(Debug, Clone, Default)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub duration: Option<f64>,
+}
+
+impl AudioTags {
+    fn merge_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let slot = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Genre) => &mut self.genre,
+                _ => continue,
+            };
+            let value = tag.value.to_string().trim().to_string();
+            if slot.is_none() && !value.is_empty() {
+                *slot = Some(value);
+            }
+        }
+    }
+}
+
+fn probe(path: &Path) -> Result<ProbeResult, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(extension);
+    }
+    
+    symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio format: {}", e))
+}
+
+/// Read title, artist, album, genre and duration without decoding the audio (blocking)
+pub fn read_tags(path: &Path) -> Result<AudioTags, String> {
+    let mut probed = probe(path)?;
+    let mut tags = AudioTags::default();
+    
+    // Container tags (Vorbis comments, MP4 atoms) take precedence over a leading ID3 tag
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.merge_revision(revision);
+    }
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            tags.merge_revision(revision);
+        }
+    }
+    
+    tags.duration = probed.format.tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .and_then(|t| Some(t.codec_params.time_base?.calc_time(t.codec_params.n_frames?)))
+        .map(|time| time.seconds as f64 + time.frac);
+    
+    Ok(tags)
+}
+
+/// Decode an audio file and feed interleaved f32 samples to `on_block`
+///
+/// Decoding stops early when the callback returns false. This is blocking and
+/// CPU heavy, so callers on the async runtime should use `spawn_blocking`.
+pub fn decode_file<F>(path: &Path, mut on_block: F) -> Result<AudioSpec, String>
+where
+    F: FnMut(&[f32], AudioSpec) -> bool,
+{
+    let mut format = probe(path)?.format;
+    
+    let track = format.tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| "No audio track found".to_string())?;
+    let track_id = track.id;
+    
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+    
+    let mut spec: Option<AudioSpec> = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Failed to read audio: {}", e)),
+        };
+        
+        if packet.track_id() != track_id {
+            continue;
+        }
+        
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt frames are common in user uploads; skip them like a player would
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        };
+        
+        let block_spec = *decoded.spec();
+        let buf = buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, block_spec));
+        if buf.capacity() < decoded.capacity() * block_spec.channels.count() {
+            *buf = SampleBuffer::new(decoded.capacity() as u64, block_spec);
+        }
+        buf.copy_interleaved_ref(decoded);
+        
+        let audio_spec = *spec.get_or_insert(AudioSpec {
+            sample_rate: block_spec.rate,
+            channels: block_spec.channels.count(),
+        });
+        
+        if !on_block(buf.samples(), audio_spec) {
+            break;
+        }
+    }
+    
+    spec.ok_or_else(|| "File contains no decodable audio".to_string())
+}
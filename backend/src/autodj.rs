@@ -0,0 +1,220 @@
+use chrono::{Duration, Utc};
+use log::{info, warn};
+use mpd_client::commands;
+use mpd_client::commands::{SongId, SongPosition};
+use mpd_client::Client as MpdClient;
+use rand::Rng;
+use std::collections::HashSet;
+
+use crate::config::env_parse;
+use crate::jingles::is_jingle;
+use crate::models::Track;
+use crate::mpd_manager::{apply_cue_range, track_id_from_filename};
+use crate::state::AppState;
+
+/// Shown as `added_by` on tracks the auto-DJ queued
+pub const AUTODJ_NAME: &str = "AutoDJ";
+
+const DEFAULT_MIN_UPCOMING: usize = 3;
+const DEFAULT_REPEAT_WINDOW_MINUTES: i64 = 120;
+const DEFAULT_ARTIST_SEPARATION: usize = 3;
+const DEFAULT_LIKE_WEIGHT: f64 = 1.0;
+
+/// Rules for filling the queue from the library when requests run low
+/// Environment variables: AUTODJ_ENABLED, AUTODJ_MIN_UPCOMING, AUTODJ_REPEAT_WINDOW_MINUTES,
+/// AUTODJ_ARTIST_SEPARATION, AUTODJ_LIKE_WEIGHT
+#[derive(Debug, Clone)]
+pub struct AutoDjConfig {
+    pub enabled: bool,
+    /// Upcoming tracks to keep queued
+    pub min_upcoming: usize,
+    /// Tracks played within this window are only picked when nothing else is left
+    pub repeat_window: Duration,
+    /// An artist isn't picked again until this many other tracks are queued after it
+    pub artist_separation: usize,
+    /// How much each like raises a track's chance of being picked
+    pub like_weight: f64,
+}
+
+impl AutoDjConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_parse("AUTODJ_ENABLED", true),
+            min_upcoming: env_parse("AUTODJ_MIN_UPCOMING", DEFAULT_MIN_UPCOMING),
+            repeat_window: Duration::minutes(env_parse("AUTODJ_REPEAT_WINDOW_MINUTES", DEFAULT_REPEAT_WINDOW_MINUTES)),
+            artist_separation: env_parse("AUTODJ_ARTIST_SEPARATION", DEFAULT_ARTIST_SEPARATION),
+            like_weight: env_parse("AUTODJ_LIKE_WEIGHT", DEFAULT_LIKE_WEIGHT).max(0.0),
+        }
+    }
+}
+
+/// A library track the auto-DJ may pick
+struct Candidate {
+    track: Track,
+    likes: u32,
+    played_recently: bool,
+}
+
+fn artist_key(track: &Track) -> Option<String> {
+    track.artist.as_ref().map(|a| a.trim().to_lowercase())
+}
+
+/// Pick up to `count` tracks by weighted random choice
+///
+/// Recently played tracks are only used once everything else is exhausted, and the
+/// artist rule is relaxed rather than leaving the queue short.
+fn choose(
+    mut candidates: Vec<Candidate>,
+    mut recent_artists: Vec<String>,
+    count: usize,
+    config: &AutoDjConfig,
+) -> Vec<Track> {
+    let mut rng = rand::thread_rng();
+    let mut picks = Vec::with_capacity(count);
+    
+    while picks.len() < count && !candidates.is_empty() {
+        let window_start = recent_artists.len().saturating_sub(config.artist_separation);
+        let blocked: HashSet<&String> = recent_artists[window_start..].iter().collect();
+        let artist_ok = |c: &Candidate| artist_key(&c.track).is_none_or(|a| !blocked.contains(&a));
+        
+        // Strictest rule set that still leaves something to pick from
+        let eligible: Vec<usize> = (0..3)
+            .map(|strictness| {
+                (0..candidates.len())
+                    .filter(|&i| {
+                        let candidate = &candidates[i];
+                        match strictness {
+                            0 => !candidate.played_recently && artist_ok(candidate),
+                            1 => !candidate.played_recently,
+                            _ => true,
+                        }
+                    })
+                    .collect::<Vec<usize>>()
+            })
+            .find(|eligible| !eligible.is_empty())
+            .unwrap_or_default();
+        
+        let weight = |i: usize| 1.0 + config.like_weight * candidates[i].likes as f64;
+        let total: f64 = eligible.iter().map(|&i| weight(i)).sum();
+        let mut target = rng.gen_range(0.0..total);
+        let mut chosen = eligible[eligible.len() - 1];
+        for &i in &eligible {
+            target -= weight(i);
+            if target < 0.0 {
+                chosen = i;
+                break;
+            }
+        }
+        
+        let candidate = candidates.swap_remove(chosen);
+        if let Some(artist) = artist_key(&candidate.track) {
+            recent_artists.push(artist);
+        }
+        picks.push(candidate.track);
+    }
+    
+    picks
+}
+
+/// Whether a queue entry was added by the auto-DJ; such entries are removed once played
+pub async fn is_autodj_pick(state: &AppState, song_id: SongId) -> bool {
+    state.queue_requesters.read().await.get(&song_id).is_some_and(|by| by == AUTODJ_NAME)
+}
+
+/// Top up the queue with library picks until `min_upcoming` tracks are waiting
+///
+/// Only tracks that haven't played yet count as upcoming: pending requests, tracks of
+/// the show on air and earlier auto-DJ picks, after the current song (or anywhere once
+/// playback has ended). Played tracks rotated back into the queue don't count. Picks are
+/// inserted right after those tracks, so they play before the rotation.
+/// Returns the ids of the queued picks.
+pub async fn fill_queue(state: &AppState, client: &MpdClient, config: &AutoDjConfig) -> Result<Vec<SongId>, String> {
+    if !config.enabled {
+        return Ok(Vec::new());
+    }
+    
+    let status = client
+        .command(commands::Status)
+        .await
+        .map_err(|e| format!("Failed to get status: {}", e))?;
+    let queue = client
+        .command(commands::Queue)
+        .await
+        .map_err(|e| format!("Failed to get queue: {}", e))?;
+    
+    let unplayed: HashSet<SongId> = {
+        let mut ids = state.programming.show_song_ids().await;
+        ids.extend(state.pending_requests.lock().await.iter().map(|r| r.song_id));
+        ids.extend(state.queue_requesters.read().await.iter()
+            .filter(|(_, by)| *by == AUTODJ_NAME)
+            .map(|(id, _)| *id));
+        ids
+    };
+    let after_current = status.current_song.map(|(position, _)| position.0 + 1).unwrap_or(0);
+    let upcoming: Vec<&str> = queue.iter()
+        .skip(after_current)
+        .filter(|s| unplayed.contains(&s.id) && !is_jingle(&s.song.url))
+        .map(|s| s.song.url.as_str())
+        .collect();
+    if upcoming.len() >= config.min_upcoming {
+        return Ok(Vec::new());
+    }
+    let needed = config.min_upcoming - upcoming.len();
+    
+    // Never pick anything already in the queue, rotating tracks included
+    let current = status.current_song
+        .and_then(|(position, _)| queue.get(position.0))
+        .map(|s| s.song.url.as_str());
+    let excluded: HashSet<&str> = queue.iter().map(|s| s.song.url.as_str()).collect();
+    
+    // Picks go after the unplayed tracks following the current song, ahead of rotation
+    let mut insert_at = after_current;
+    while queue.get(insert_at).is_some_and(|s| unplayed.contains(&s.id) || is_jingle(&s.song.url)) {
+        insert_at += 1;
+    }
+    
+    let recent_artists: Vec<String> = {
+        let metadata = state.tracks_metadata.read().await;
+        current.into_iter()
+            .chain(upcoming.iter().copied())
+            .filter_map(|filename| metadata.get(&track_id_from_filename(filename)).and_then(artist_key))
+            .collect()
+    };
+    
+    let candidates: Vec<Candidate> = {
+        let metadata = state.tracks_metadata.read().await;
+        let stats = state.track_stats.read().await;
+        let repeat_cutoff = Utc::now() - config.repeat_window;
+        metadata.values()
+            .filter(|track| !excluded.contains(track.filename.as_str()))
+            .map(|track| {
+                let track_stats = stats.get(&track.id);
+                Candidate {
+                    track: track.clone(),
+                    likes: track_stats.map(|s| s.likes()).unwrap_or(0),
+                    played_recently: track_stats
+                        .and_then(|s| s.last_played_at)
+                        .is_some_and(|t| t > repeat_cutoff),
+                }
+            })
+            .collect()
+    };
+    
+    let mut added = Vec::new();
+    for track in choose(candidates, recent_artists, needed, config) {
+        let position = SongPosition(insert_at + added.len());
+        let song_id = match client.command(commands::Add::uri(&track.filename).at(position)).await {
+            Ok(song_id) => song_id,
+            Err(e) => {
+                warn!("Auto-DJ failed to queue {}: {}", track.filename, e);
+                continue;
+            }
+        };
+        apply_cue_range(state, client, song_id, &track.filename).await;
+        state.queue_requesters.write().await.insert(song_id, AUTODJ_NAME.to_string());
+        info!("Auto-DJ queued {}", track.filename);
+        added.push(song_id);
+    }
+    
+    Ok(added)
+}
@@ -0,0 +1,130 @@
+use log::{error, info};
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+use crate::audio::{read_tags, AudioTags};
+use crate::models::Track;
+use crate::mpd_manager::{extract_username_from_filename, parse_metadata_from_filename};
+use crate::state::AppState;
+use crate::storage::StoredFile;
+
+const DEFAULT_CATALOG_PATH: &str = "data/catalog.json";
+
+/// Serializes catalog writes so two saves never interleave
+static SAVE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Where the track catalog is persisted
+/// Environment variable: CATALOG_PATH
+fn catalog_path() -> PathBuf {
+    PathBuf::from(std::env::var("CATALOG_PATH").unwrap_or_else(|_| DEFAULT_CATALOG_PATH.to_string()))
+}
+
+/// Load the persisted catalog, keeping only tracks whose file is still in storage
+pub async fn load_catalog(state: &AppState) {
+    let path = catalog_path();
+    let tracks: Vec<Track> = match tokio::fs::read(&path).await {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                error!("Failed to parse catalog {:?}: {}", path, e);
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Failed to read catalog {:?}: {}", path, e);
+            return;
+        }
+    };
+    
+    let total = tracks.len();
+    let mut metadata = state.tracks_metadata.write().await;
+    for track in tracks {
+        if state.storage.get(&track.filename).await.is_some() {
+            state.search_index.insert(&track).await;
+            metadata.insert(track.id.clone(), track);
+        }
+    }
+    info!("Loaded {} of {} catalog tracks", metadata.len(), total);
+}
+
+/// Write the catalog to disk; the previous copy is replaced atomically
+pub async fn save_catalog(state: &AppState) {
+    let _guard = SAVE_LOCK.lock().await;
+    
+    let data = {
+        let metadata = state.tracks_metadata.read().await;
+        let tracks: Vec<&Track> = metadata.values().collect();
+        match serde_json::to_vec_pretty(&tracks) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize catalog: {}", e);
+                return;
+            }
+        }
+    };
+    
+    let path = catalog_path();
+    let tmp_path = path.with_extension("json.tmp");
+    let result = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await
+    }.await;
+    
+    if let Err(e) = result {
+        error!("Failed to save catalog {:?}: {}", path, e);
+    }
+}
+
+/// Catalog entry for an upload that has none, built from its tags and filename
+async fn track_from_file(state: &AppState, file: &StoredFile) -> Track {
+    let path = state.storage.path(&file.filename);
+    let tags = match tokio::task::spawn_blocking(move || read_tags(&path)).await {
+        Ok(Ok(tags)) => tags,
+        _ => AudioTags::default(),
+    };
+    let (parsed_artist, parsed_title) = parse_metadata_from_filename(&file.filename);
+    
+    Track {
+        id: file.track_id.clone(),
+        filename: file.filename.clone(),
+        title: tags.title.or(parsed_title),
+        artist: tags.artist.or(parsed_artist),
+        album: tags.album,
+        genre: tags.genre,
+        duration: tags.duration,
+        added_by: extract_username_from_filename(&file.filename).unwrap_or_else(|| "Unknown".to_string()),
+        added_at: file.modified,
+        loudness: None,
+        cue_in: None,
+        cue_out: None,
+        analysis_version: 0,
+        waveform_version: 0,
+        // Recorded before analysis rewrites the tags, so re-uploads of this file match it
+        upload_hash: Some(file.content_hash.clone()),
+        lyrics: None,
+        possible_duplicate_of: None,
+    }
+}
+
+/// Add catalog entries for uploads that predate the catalog or were copied in by hand
+pub async fn backfill_catalog(state: &AppState) {
+    let mut added = 0;
+    for file in state.storage.files().await {
+        if state.tracks_metadata.read().await.contains_key(&file.track_id) {
+            continue;
+        }
+        let track = track_from_file(state, &file).await;
+        state.search_index.insert(&track).await;
+        state.tracks_metadata.write().await.insert(track.id.clone(), track);
+        added += 1;
+    }
+    
+    if added > 0 {
+        info!("Added {} existing uploads to the catalog", added);
+        save_catalog(state).await;
+    }
+}
@@ -0,0 +1,13 @@
+use log::warn;
+
+/// Read a setting from the environment, falling back to `default` when it is unset
+/// or can't be parsed
+pub fn env_parse<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(val) => val.trim().parse().unwrap_or_else(|_| {
+            warn!("Invalid {} value: '{}', using default", name, val);
+            default
+        }),
+        Err(_) => default,
+    }
+}
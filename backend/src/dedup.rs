@@ -0,0 +1,320 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::RwLock;
+
+use crate::audio::{decode_file, read_tags};
+use crate::state::AppState;
+use crate::storage::{StorageManager, StoredFile};
+
+/// Length of one fingerprint frame
+const FRAME_SECS: f64 = 0.1;
+/// Frames summed into one loudness window, long enough to cover whole beats
+const WINDOW_FRAMES: usize = 10;
+/// Only the beginning of a track is fingerprinted
+const MAX_FINGERPRINT_SECS: f64 = 120.0;
+/// Share of matching bits above which two fingerprints are likely the same recording
+const MATCH_THRESHOLD: f64 = 0.85;
+/// Maximum difference in duration between two encodes of the same recording
+const MAX_DURATION_DIFF_SECS: f64 = 3.0;
+/// Frames of misalignment tolerated between two encodes (encoder delay, padding)
+const MAX_FRAME_SHIFT: usize = 3;
+
+/// Whether uploads are also compared by acoustic fingerprint
+/// Environment variable: DEDUP_FINGERPRINT (true/false)
+pub fn fingerprint_enabled() -> bool {
+    std::env::var("DEDUP_FINGERPRINT")
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Coarse acoustic fingerprint: one bit per frame telling whether the following
+/// window is louder than the one starting at that frame
+///
+/// The energy envelope survives re-encoding (other bitrate or codec) well, so two
+/// encodes of the same recording produce nearly identical bit strings. Windows span
+/// whole beats, so songs that merely share a tempo don't look alike. It is still far
+/// from a spectral fingerprint, so a match is only ever a possible duplicate.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub duration_secs: f64,
+    bits: Vec<bool>,
+}
+
+impl Fingerprint {
+    /// Decode a file and compute its fingerprint (blocking)
+    /// Decoding stops after the fingerprinted part when the container knows the duration
+    pub fn compute(path: &Path) -> Result<Fingerprint, String> {
+        let container_duration = read_tags(path).ok().and_then(|tags| tags.duration);
+        let mut energies = Vec::new();
+        let mut frame_energy = 0.0f64;
+        let mut frame_len = 0usize;
+        let mut total_samples = 0usize;
+        
+        let spec = decode_file(path, |samples, spec| {
+            let frame_size = (spec.sample_rate as f64 * FRAME_SECS) as usize;
+            let max_frames = (MAX_FINGERPRINT_SECS / FRAME_SECS) as usize;
+            
+            for frame in samples.chunks(spec.channels) {
+                let mono: f32 = frame.iter().sum::<f32>() / spec.channels as f32;
+                total_samples += 1;
+                if energies.len() >= max_frames {
+                    if container_duration.is_some() {
+                        return false;
+                    }
+                    // Otherwise keep decoding to count the samples
+                    continue;
+                }
+                frame_energy += (mono * mono) as f64;
+                frame_len += 1;
+                if frame_len >= frame_size {
+                    energies.push(frame_energy / frame_len as f64);
+                    frame_energy = 0.0;
+                    frame_len = 0;
+                }
+            }
+            true
+        })?;
+        
+        let duration_secs = container_duration.unwrap_or(total_samples as f64 / spec.sample_rate as f64);
+        Ok(Fingerprint::from_energies(duration_secs, &energies))
+    }
+    
+    /// Fingerprint of a sequence of frame energies
+    fn from_energies(duration_secs: f64, energies: &[f64]) -> Fingerprint {
+        let windows: Vec<f64> = energies.windows(WINDOW_FRAMES).map(|w| w.iter().sum()).collect();
+        Fingerprint {
+            duration_secs,
+            bits: windows.iter().zip(windows.iter().skip(WINDOW_FRAMES)).map(|(a, b)| b > a).collect(),
+        }
+    }
+    
+    /// Best share of matching bits over small alignments, 0.0 to 1.0
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let mut best = 0.0f64;
+        for shift in 0..=MAX_FRAME_SHIFT {
+            for (a, b) in [(&self.bits, &other.bits), (&other.bits, &self.bits)] {
+                let a = &a[shift.min(a.len())..];
+                let len = a.len().min(b.len());
+                if len == 0 {
+                    continue;
+                }
+                let matching = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+                best = best.max(matching as f64 / len as f64);
+            }
+        }
+        best
+    }
+    
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        (self.duration_secs - other.duration_secs).abs() <= MAX_DURATION_DIFF_SECS
+            && self.similarity(other) >= MATCH_THRESHOLD
+    }
+}
+
+/// Fingerprints of the files in the uploads directory, keyed by filename
+#[derive(Default)]
+pub struct FingerprintIndex {
+    fingerprints: RwLock<HashMap<String, Fingerprint>>,
+}
+
+impl FingerprintIndex {
+    pub async fn insert(&self, filename: &str, fingerprint: Fingerprint) {
+        self.fingerprints.write().await.insert(filename.to_string(), fingerprint);
+    }
+    
+    pub async fn remove(&self, filename: &str) {
+        self.fingerprints.write().await.remove(filename);
+    }
+    
+    /// First matching upload that is still in storage
+    async fn find_match(&self, fingerprint: &Fingerprint, storage: &StorageManager) -> Option<StoredFile> {
+        let matches: Vec<String> = self.fingerprints.read().await
+            .iter()
+            .filter(|(_, known)| known.matches(fingerprint))
+            .map(|(filename, _)| filename.clone())
+            .collect();
+        for filename in matches {
+            if let Some(stored) = storage.get(&filename).await {
+                return Some(stored);
+            }
+        }
+        None
+    }
+    
+    async fn contains(&self, filename: &str) -> bool {
+        self.fingerprints.read().await.contains_key(filename)
+    }
+}
+
+async fn compute_fingerprint(state: &AppState, filename: &str) -> Result<Fingerprint, String> {
+    let path = state.storage.path(filename);
+    tokio::task::spawn_blocking(move || Fingerprint::compute(&path))
+        .await
+        .map_err(|e| format!("Fingerprint task failed: {}", e))?
+}
+
+/// Fingerprint every upload that isn't indexed yet (runs in the background at startup)
+pub async fn start_fingerprint_indexer(state: AppState) {
+    if !fingerprint_enabled() {
+        return;
+    }
+    
+    tokio::spawn(async move {
+        let mut indexed = 0;
+        for file in state.storage.files().await {
+            if state.fingerprints.contains(&file.filename).await {
+                continue;
+            }
+            match compute_fingerprint(&state, &file.filename).await {
+                Ok(fingerprint) => {
+                    state.fingerprints.insert(&file.filename, fingerprint).await;
+                    indexed += 1;
+                }
+                Err(e) => warn!("Failed to fingerprint {}: {}", file.filename, e),
+            }
+        }
+        info!("Fingerprinted {} existing uploads", indexed);
+    });
+}
+
+/// Find an existing upload with the same content as a freshly written file
+///
+/// Matches are found by content hash, also against the hash analyzed files had before
+/// they were retagged.
+pub async fn find_duplicate(state: &AppState, filename: &str, content_hash: &str) -> Option<StoredFile> {
+    if let Some(existing) = state.storage.find_by_hash(content_hash).await {
+        if existing.filename != filename {
+            info!("Upload {} has the same content as {}", filename, existing.filename);
+            return Some(existing);
+        }
+    }
+    
+    // Analyzed files were retagged, so also compare with their hash as uploaded
+    let retagged = state.tracks_metadata.read().await
+        .values()
+        .find(|t| t.filename != filename && t.upload_hash.as_deref() == Some(content_hash))
+        .map(|t| t.filename.clone());
+    if let Some(existing) = retagged {
+        if let Some(stored) = state.storage.get(&existing).await {
+            info!("Upload {} has the same content as {} before tagging", filename, existing);
+            return Some(stored);
+        }
+    }
+    
+    None
+}
+
+/// With DEDUP_FINGERPRINT enabled, find an existing upload that sounds like a newly
+/// stored one and index the new file's fingerprint for future uploads
+///
+/// A match is only a possible duplicate (e.g. a re-encode): the new file is kept and
+/// flagged, never dropped.
+pub async fn find_similar(state: &AppState, filename: &str) -> Option<StoredFile> {
+    if !fingerprint_enabled() {
+        return None;
+    }
+    
+    let fingerprint = match compute_fingerprint(state, filename).await {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            warn!("Failed to fingerprint {}: {}", filename, e);
+            return None;
+        }
+    };
+    
+    let similar = state.fingerprints.find_match(&fingerprint, &state.storage).await;
+    if let Some(stored) = &similar {
+        info!("Upload {} sounds like {}, flagging it as a possible duplicate", filename, stored.filename);
+    }
+    state.fingerprints.insert(filename, fingerprint).await;
+    similar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn fingerprint(duration_secs: f64, pattern: &[u8], repeat: usize) -> Fingerprint {
+        Fingerprint {
+            duration_secs,
+            bits: pattern.iter().cycle().take(pattern.len() * repeat).map(|b| *b == 1).collect(),
+        }
+    }
+    
+    #[test]
+    fn identical_fingerprints_match() {
+        let a = fingerprint(200.0, &[1, 0, 0, 1, 1, 0, 1], 100);
+        assert_eq!(a.similarity(&a), 1.0);
+        assert!(a.matches(&a.clone()));
+    }
+    
+    #[test]
+    fn small_misalignments_are_tolerated() {
+        let a = fingerprint(200.0, &[1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 1], 100);
+        let mut shifted = a.clone();
+        shifted.bits.drain(..MAX_FRAME_SHIFT);
+        assert_eq!(a.similarity(&shifted), 1.0);
+        assert_eq!(shifted.similarity(&a), 1.0);
+    }
+    
+    #[test]
+    fn different_recordings_do_not_match() {
+        let a = fingerprint(200.0, &[1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 1], 100);
+        let b = fingerprint(200.0, &[0, 1, 1, 0, 0, 1, 0, 1, 1, 1, 0], 100);
+        assert!(a.similarity(&b) < MATCH_THRESHOLD);
+        assert!(!a.matches(&b));
+    }
+    
+    #[test]
+    fn durations_must_be_close() {
+        let a = fingerprint(200.0, &[1, 0, 1, 1], 100);
+        let mut longer = a.clone();
+        longer.duration_secs += MAX_DURATION_DIFF_SECS + 0.5;
+        assert!(!a.matches(&longer));
+        longer.duration_secs = 200.0 + MAX_DURATION_DIFF_SECS;
+        assert!(a.matches(&longer));
+    }
+    
+    #[test]
+    fn empty_fingerprints_are_not_similar() {
+        let empty = fingerprint(1.0, &[], 0);
+        assert_eq!(empty.similarity(&empty), 0.0);
+        assert!(!empty.matches(&empty));
+    }
+    
+    /// Frame energies of a pop song: a kick every `beat` frames decaying until the next,
+    /// off-beat hits at `offbeat` frames into each beat, section loudness changing every
+    /// 8 bars and small per-song variation from `seed`
+    fn song_envelope(seed: u64, beat: usize, offbeat: usize, sections: &[f64]) -> Vec<f64> {
+        let mut rng = seed;
+        let frames = (MAX_FINGERPRINT_SECS / FRAME_SECS) as usize;
+        (0..frames)
+            .map(|i| {
+                rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let noise = (rng >> 33) as f64 / (1u64 << 31) as f64;
+                let section = sections[(i / (beat * 32)) % sections.len()];
+                let phase = i % beat;
+                let hit = if phase == 0 { 1.0 } else if phase == offbeat { 0.6 } else { 0.0 };
+                section * (0.2 + hit + 0.5 * (-(phase as f64) / 2.0).exp()) * (0.8 + 0.4 * noise)
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn different_songs_with_the_same_tempo_and_length_do_not_match() {
+        let a = Fingerprint::from_energies(210.0, &song_envelope(1, 5, 3, &[0.5, 0.8, 1.0, 0.7]));
+        let b = Fingerprint::from_energies(211.0, &song_envelope(2, 5, 2, &[0.6, 1.0, 0.6, 0.9]));
+        // Same arrangement, different performance
+        let c = Fingerprint::from_energies(210.0, &song_envelope(3, 5, 3, &[0.5, 0.8, 1.0, 0.7]));
+        assert!(!a.matches(&b));
+        assert!(!a.matches(&c));
+        
+        // A re-encode of the same song changes each frame's energy only slightly
+        let reencoded: Vec<f64> = song_envelope(1, 5, 3, &[0.5, 0.8, 1.0, 0.7]).iter()
+            .enumerate()
+            .map(|(i, e)| e * if i % 3 == 0 { 1.01 } else { 0.99 })
+            .collect();
+        assert!(a.matches(&Fingerprint::from_energies(209.0, &reencoded)));
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::models::{CurrentTrack, PlaybackState};
+use crate::state::AppState;
+
+/// Lead time so clients can fetch the file before they have to start it
+const DIRECT_PLAY_LEAD_MS: i64 = 1000;
+/// Drift of the track start beyond which a playing track counts as seeked
+const SEEK_TOLERANCE_SECS: f64 = 1.0;
+
+/// What direct-play clients should do, sent as a `direct_play` WebSocket event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DirectPlayCommand {
+    /// Play the original file so that it is `offset` seconds in at server time `at`
+    Play {
+        track_id: String,
+        url: String,
+        at: DateTime<Utc>,
+        offset: f64,
+    },
+    /// Hold the original file at `offset` seconds
+    Pause {
+        track_id: String,
+        url: String,
+        offset: f64,
+    },
+    Stop,
+    /// The current item has no original file (e.g. a jingle); use `/api/stream` until the next command
+    Stream,
+}
+
+/// The playback state direct-play clients were last told about
+#[derive(Debug, Clone)]
+struct SyncPoint {
+    track_id: Option<String>,
+    state: PlaybackState,
+    elapsed: Option<f64>,
+    started_at: Option<DateTime<Utc>>,
+}
+
+impl SyncPoint {
+    fn of(current: &CurrentTrack) -> Self {
+        Self {
+            track_id: current.track.as_ref().map(|t| t.id.clone()),
+            state: current.state.clone(),
+            elapsed: current.elapsed,
+            started_at: current.started_at,
+        }
+    }
+    
+    /// Whether clients following `self` have to be corrected to reach `other`
+    fn differs(&self, other: &SyncPoint) -> bool {
+        if self.track_id != other.track_id || self.state != other.state {
+            return true;
+        }
+        // A playing track keeps its start instant and a paused one its position, unless seeked
+        let drift = match self.state {
+            PlaybackState::Playing => self.started_at.zip(other.started_at)
+                .map(|(a, b)| (a - b).num_milliseconds().abs() as f64 / 1000.0),
+            PlaybackState::Paused => self.elapsed.zip(other.elapsed).map(|(a, b)| (a - b).abs()),
+            PlaybackState::Stopped => None,
+        };
+        drift.is_some_and(|drift| drift > SEEK_TOLERANCE_SECS)
+    }
+}
+
+/// Tracks the last command so it is only resent when playback actually changes
+#[derive(Default)]
+pub struct DirectPlaySync {
+    last: Mutex<Option<SyncPoint>>,
+}
+
+/// URL of a track's original upload
+pub fn file_url(track_id: &str) -> String {
+    format!("/api/tracks/{}/file", track_id)
+}
+
+/// The command that puts a direct-play client in step with `current`
+pub async fn direct_play_command(state: &AppState, current: &CurrentTrack) -> DirectPlayCommand {
+    let Some(track) = current.track.as_ref() else {
+        return DirectPlayCommand::Stop;
+    };
+    if state.storage.get(&track.filename).await.is_none() {
+        return DirectPlayCommand::Stream;
+    }
+    
+    let offset = current.elapsed.unwrap_or(0.0);
+    match current.state {
+        PlaybackState::Playing => {
+            let lead = Duration::milliseconds(DIRECT_PLAY_LEAD_MS);
+            DirectPlayCommand::Play {
+                track_id: track.id.clone(),
+                url: file_url(&track.id),
+                at: current.server_time + lead,
+                offset: offset + DIRECT_PLAY_LEAD_MS as f64 / 1000.0,
+            }
+        }
+        PlaybackState::Paused => DirectPlayCommand::Pause {
+            track_id: track.id.clone(),
+            url: file_url(&track.id),
+            offset,
+        },
+        PlaybackState::Stopped => DirectPlayCommand::Stop,
+    }
+}
+
+pub fn direct_play_message(command: &DirectPlayCommand) -> String {
+    serde_json::json!({
+        "type": "direct_play",
+        "data": command
+    }).to_string()
+}
+
+/// Send opted-in clients a new command if the track, play state or position changed
+pub async fn sync_direct_play(state: &AppState, current: &CurrentTrack) {
+    let point = SyncPoint::of(current);
+    {
+        let mut last = state.direct_play.last.lock().await;
+        if last.as_ref().is_some_and(|last| !last.differs(&point)) {
+            return;
+        }
+        *last = Some(point);
+    }
+    
+    let command = direct_play_command(state, current).await;
+    state.broadcast_direct_play(&direct_play_message(&command)).await;
+}
@@ -0,0 +1,324 @@
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use mpd_client::commands;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+use crate::analysis::remove_waveform;
+use crate::catalog::save_catalog;
+use crate::jingles::is_jingle;
+use crate::lyrics::remove_lyrics;
+use crate::mpd_manager::track_id_from_filename;
+use crate::state::AppState;
+use crate::storage::Reservation;
+use crate::stats::save_track_stats;
+
+const DEFAULT_PROTECTED_TRACKS: usize = 3;
+
+/// Serializes evictions so concurrent uploads don't each free the same shortfall
+static EVICTION_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A file in the uploads directory that may be deleted to free space
+#[derive(Debug, Clone)]
+pub struct EvictionCandidate {
+    pub filename: String,
+    pub size: u64,
+    pub uploaded_at: DateTime<Utc>,
+    pub last_played_at: Option<DateTime<Utc>>,
+    pub plays: u32,
+    pub likes: u32,
+}
+
+/// Decides which files go first when storage is full
+pub trait EvictionPolicy: Send + Sync {
+    fn name(&self) -> &'static str;
+    
+    /// Sort candidates so the first one is the first to be evicted
+    fn rank(&self, candidates: &mut [EvictionCandidate]);
+}
+
+/// Delete the files that were uploaded first
+pub struct OldestUpload;
+
+impl EvictionPolicy for OldestUpload {
+    fn name(&self) -> &'static str {
+        "oldest-upload"
+    }
+    
+    fn rank(&self, candidates: &mut [EvictionCandidate]) {
+        candidates.sort_by_key(|c| c.uploaded_at);
+    }
+}
+
+/// Delete the files that haven't been heard for the longest time
+/// Tracks that never played count as played when they were uploaded
+pub struct LeastRecentlyPlayed;
+
+impl EvictionPolicy for LeastRecentlyPlayed {
+    fn name(&self) -> &'static str {
+        "least-recently-played"
+    }
+    
+    fn rank(&self, candidates: &mut [EvictionCandidate]) {
+        candidates.sort_by_key(|c| c.last_played_at.unwrap_or(c.uploaded_at));
+    }
+}
+
+/// Delete the least liked files first; among equally liked ones the least played,
+/// then the oldest
+pub struct LowestRated;
+
+impl EvictionPolicy for LowestRated {
+    fn name(&self) -> &'static str {
+        "lowest-rated"
+    }
+    
+    fn rank(&self, candidates: &mut [EvictionCandidate]) {
+        candidates.sort_by(|a, b| {
+            a.likes.cmp(&b.likes)
+                .then(a.plays.cmp(&b.plays))
+                .then(a.uploaded_at.cmp(&b.uploaded_at))
+        });
+    }
+}
+
+/// Delete the biggest files first, so as few tracks as possible are lost
+pub struct LargestFirst;
+
+impl EvictionPolicy for LargestFirst {
+    fn name(&self) -> &'static str {
+        "largest-first"
+    }
+    
+    fn rank(&self, candidates: &mut [EvictionCandidate]) {
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.size));
+    }
+}
+
+/// Get the eviction policy from environment variable or use default
+/// Environment variable: EVICTION_POLICY (oldest-upload, least-recently-played, lowest-rated, largest-first)
+pub fn get_eviction_policy() -> Box<dyn EvictionPolicy> {
+    match std::env::var("EVICTION_POLICY") {
+        Ok(val) => match val.trim().to_lowercase().as_str() {
+            "oldest-upload" => Box::new(OldestUpload),
+            "least-recently-played" => Box::new(LeastRecentlyPlayed),
+            "lowest-rated" => Box::new(LowestRated),
+            "largest-first" => Box::new(LargestFirst),
+            _ => {
+                warn!("Unknown EVICTION_POLICY '{}', using oldest-upload", val);
+                Box::new(OldestUpload)
+            }
+        },
+        Err(_) => Box::new(OldestUpload),
+    }
+}
+
+/// Number of upcoming tracks (after the current one) that are never evicted
+/// Environment variable: EVICTION_PROTECTED_TRACKS
+pub fn get_protected_track_count() -> usize {
+    std::env::var("EVICTION_PROTECTED_TRACKS")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(DEFAULT_PROTECTED_TRACKS)
+}
+
+/// Filenames of the current track and the next N queued tracks
+async fn get_protected_files(state: &AppState) -> Result<HashSet<String>, String> {
+    let client = state.mpd_client.lock().await;
+    
+    let status = client
+        .command(commands::Status)
+        .await
+        .map_err(|e| format!("Failed to get status: {}", e))?;
+    
+    let queue = client
+        .command(commands::Queue)
+        .await
+        .map_err(|e| format!("Failed to get queue: {}", e))?;
+    
+    let start = status.current_song.map(|(pos, _)| pos.0).unwrap_or(0);
+    let protected_count = get_protected_track_count() + 1;
+    
+    Ok(queue.iter()
+        .cycle()
+        .skip(start)
+        .take(protected_count.min(queue.len()))
+        .map(|s| s.song.url.clone())
+        .collect())
+}
+
+/// List the uploads that the policy may choose from
+async fn get_eviction_candidates(state: &AppState, protected: &HashSet<String>) -> Vec<EvictionCandidate> {
+    let stats = state.track_stats.read().await;
+    let metadata = state.tracks_metadata.read().await;
+    
+    state.storage.files().await
+        .into_iter()
+        // Skip anything that's playing or about to play, and station jingles
+        .filter(|file| !protected.contains(&file.filename) && !is_jingle(&file.filename))
+        .map(|file| {
+            let track_stats = stats.get(&file.track_id).cloned().unwrap_or_default();
+            // Tagging rewrites files, so the catalog is the reliable record of upload time
+            let uploaded_at = metadata.get(&file.track_id).map(|t| t.added_at).unwrap_or(file.modified);
+            EvictionCandidate {
+                filename: file.filename,
+                size: file.size,
+                uploaded_at,
+                last_played_at: track_stats.last_played_at,
+                plays: track_stats.plays,
+                likes: track_stats.likes(),
+            }
+        })
+        .collect()
+}
+
+/// Delete an upload from disk and drop every queue entry that points to it
+async fn evict_file(state: &AppState, filename: &str) -> std::io::Result<()> {
+    state.storage.remove(filename).await?;
+    state.fingerprints.remove(filename).await;
+    
+    {
+        let client = state.mpd_client.lock().await;
+        match client.command(commands::Queue).await {
+            Ok(queue) => {
+                for song in queue.iter().filter(|s| s.song.url == filename) {
+                    if let Err(e) = client.command(commands::Delete::id(song.id)).await {
+                        warn!("Failed to remove evicted track from queue: {}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to get queue while evicting {}: {}", filename, e),
+        }
+    }
+    
+    let track_id = track_id_from_filename(filename);
+    state.tracks_metadata.write().await.remove(&track_id);
+    state.track_stats.write().await.remove(&track_id);
+    save_track_stats(state).await;
+    state.search_index.remove(&track_id).await;
+    remove_waveform(&track_id).await;
+    remove_lyrics(&track_id).await;
+    save_catalog(state).await;
+    
+    Ok(())
+}
+
+/// Check that `size` more bytes can be made to fit in storage and set them aside
+///
+/// Nothing is evicted yet: files are evicted one at a time as they are stored, and the
+/// reservation keeps concurrent uploads from taking the room in the meantime.
+pub async fn reserve_space(state: &AppState, size: u64) -> Option<Reservation> {
+    let _guard = EVICTION_LOCK.lock().await;
+    let max_storage = state.storage.max_total();
+    let current_size = state.storage.total_size().await + state.storage.reserved();
+    
+    // Other reservations may already be counting on evictions, so nothing is clamped here
+    if current_size + size > max_storage {
+        let protected = match get_protected_files(state).await {
+            Ok(protected) => protected,
+            Err(e) => {
+                error!("Cannot determine protected tracks, refusing to reserve space: {}", e);
+                return None;
+            }
+        };
+        let evictable: u64 = get_eviction_candidates(state, &protected).await.iter().map(|c| c.size).sum();
+        if current_size + size > max_storage + evictable {
+            warn!("Cannot reserve {} bytes: {} used or reserved, {} evictable", size, current_size, evictable);
+            return None;
+        }
+    }
+    
+    Some(state.storage.reserve(size))
+}
+
+/// Evict files with the configured policy until `needed_size` more bytes fit in storage
+/// The current track and the next few queued tracks are never evicted, and space
+/// reserved by other uploads counts as used
+pub async fn free_up_space(state: &AppState, needed_size: u64) -> std::io::Result<bool> {
+    let _guard = EVICTION_LOCK.lock().await;
+    let max_storage = state.storage.max_total();
+    let current_size = state.storage.total_size().await + state.storage.reserved();
+    
+    // If already enough space, no need to delete
+    if current_size + needed_size <= max_storage {
+        return Ok(true);
+    }
+    
+    let to_free = (current_size + needed_size) - max_storage;
+    let policy = get_eviction_policy();
+    
+    info!("Need to free up {} bytes to accommodate new upload (policy: {})", to_free, policy.name());
+    
+    let protected = match get_protected_files(state).await {
+        Ok(protected) => protected,
+        Err(e) => {
+            // Without knowing what's playing, deleting anything could cut the stream
+            error!("Cannot determine protected tracks, refusing to evict: {}", e);
+            return Ok(false);
+        }
+    };
+    
+    let mut candidates = get_eviction_candidates(state, &protected).await;
+    policy.rank(&mut candidates);
+    
+    let mut freed = 0u64;
+    for candidate in candidates {
+        if freed >= to_free {
+            break;
+        }
+        
+        info!("Evicting {} to free space ({} bytes)", candidate.filename, candidate.size);
+        if let Err(e) = evict_file(state, &candidate.filename).await {
+            error!("Failed to evict {}: {}", candidate.filename, e);
+            continue;
+        }
+        freed += candidate.size;
+    }
+    
+    if freed > 0 {
+        let queue_update = serde_json::json!({
+            "type": "queue_update",
+            "data": {}
+        });
+        state.broadcast_message(&queue_update.to_string()).await;
+    }
+    
+    if freed < to_free {
+        warn!("No more evictable files but still need {} bytes", to_free - freed);
+        return Ok(false);
+    }
+    
+    info!("Successfully freed {} bytes", freed);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    
+    fn candidate(filename: &str, likes: u32, plays: u32, days_old: i64) -> EvictionCandidate {
+        EvictionCandidate {
+            filename: filename.to_string(),
+            size: 1,
+            uploaded_at: Utc::now() - Duration::days(days_old),
+            last_played_at: None,
+            plays,
+            likes,
+        }
+    }
+    
+    #[test]
+    fn lowest_rated_evicts_unliked_then_unplayed_then_old() {
+        let mut candidates = vec![
+            candidate("liked", 3, 0, 10),
+            candidate("popular", 0, 50, 10),
+            candidate("new", 0, 2, 1),
+            candidate("old", 0, 2, 5),
+            candidate("unheard", 0, 0, 1),
+        ];
+        LowestRated.rank(&mut candidates);
+        let order: Vec<&str> = candidates.iter().map(|c| c.filename.as_str()).collect();
+        assert_eq!(order, vec!["unheard", "old", "new", "popular", "liked"]);
+    }
+}
@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::models::Track;
+
+const DEFAULT_HISTORY_PATH: &str = "data/history.json";
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// A track as it was when it went on air
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub track: Track,
+    pub played_at: DateTime<Utc>,
+}
+
+/// The most recently played tracks, newest last, persisted across restarts
+/// Environment variables: HISTORY_PATH, HISTORY_SIZE
+pub struct PlayHistory {
+    path: PathBuf,
+    max_entries: usize,
+    entries: RwLock<VecDeque<HistoryEntry>>,
+    save_lock: Mutex<()>,
+}
+
+impl PlayHistory {
+    pub fn from_env() -> Self {
+        Self {
+            path: PathBuf::from(std::env::var("HISTORY_PATH").unwrap_or_else(|_| DEFAULT_HISTORY_PATH.to_string())),
+            max_entries: std::env::var("HISTORY_SIZE")
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(DEFAULT_HISTORY_SIZE),
+            entries: RwLock::new(VecDeque::new()),
+            save_lock: Mutex::new(()),
+        }
+    }
+    
+    pub async fn load(&self) {
+        let entries: VecDeque<HistoryEntry> = match tokio::fs::read(&self.path).await {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("Failed to parse play history {:?}: {}", self.path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Failed to read play history {:?}: {}", self.path, e);
+                return;
+            }
+        };
+        
+        info!("Loaded {} play history entries", entries.len());
+        *self.entries.write().await = entries;
+    }
+    
+    async fn save(&self) {
+        let _guard = self.save_lock.lock().await;
+        let data = match serde_json::to_vec(&*self.entries.read().await) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize play history: {}", e);
+                return;
+            }
+        };
+        
+        let tmp_path = self.path.with_extension("json.tmp");
+        let result = async {
+            if let Some(parent) = self.path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&tmp_path, data).await?;
+            tokio::fs::rename(&tmp_path, &self.path).await
+        }.await;
+        
+        if let Err(e) = result {
+            error!("Failed to save play history {:?}: {}", self.path, e);
+        }
+    }
+    
+    /// Add a track that just went on air
+    /// The history is saved in the background, so the monitor doesn't wait on the disk
+    /// while it holds the MPD connection
+    pub async fn record(self: &Arc<Self>, track: Track) {
+        {
+            let mut entries = self.entries.write().await;
+            entries.push_back(HistoryEntry {
+                track,
+                played_at: Utc::now(),
+            });
+            while entries.len() > self.max_entries {
+                entries.pop_front();
+            }
+        }
+        let history = Arc::clone(self);
+        tokio::spawn(async move { history.save().await });
+    }
+    
+    /// When a catalog track last went on air, if it's still in the history
+    pub async fn last_played_at(&self, track_id: &str) -> Option<DateTime<Utc>> {
+        self.entries.read().await.iter().rev().find(|e| e.track.id == track_id).map(|e| e.played_at)
+    }
+    
+    /// Up to `limit` entries, newest first
+    pub async fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.entries.read().await.iter().rev().take(limit).cloned().collect()
+    }
+}
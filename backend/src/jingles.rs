@@ -0,0 +1,153 @@
+use chrono::{DateTime, Duration, Local, Timelike, Utc};
+use log::info;
+use mpd_client::commands;
+use mpd_client::commands::SongPosition;
+use mpd_client::responses::SongInQueue;
+use mpd_client::Client as MpdClient;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+
+use crate::config::env_parse;
+use crate::state::AppState;
+
+/// Jingles live in this subdirectory of the uploads directory. The storage index only
+/// lists top-level files, so they are never library tracks or eviction candidates.
+pub const JINGLE_DIR: &str = "jingles";
+
+/// Whether a queue entry is a jingle rather than a track
+pub fn is_jingle(filename: &str) -> bool {
+    filename.strip_prefix(JINGLE_DIR).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// MPD path of a jingle file
+pub fn jingle_path(name: &str) -> String {
+    format!("{}/{}", JINGLE_DIR, name)
+}
+
+/// Names of the available jingle files, sorted
+pub async fn list_jingles(state: &AppState) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut entries = match tokio::fs::read_dir(state.storage.path(JINGLE_DIR)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with('.') && entry.file_type().await?.is_file() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// When jingles are played between tracks; rules combine, and 0 disables one
+/// Environment variables: JINGLE_EVERY_TRACKS, JINGLE_EVERY_MINUTES, JINGLE_TOP_OF_HOUR
+#[derive(Debug, Clone, Serialize)]
+pub struct JingleRules {
+    pub every_tracks: u32,
+    pub every_minutes: u32,
+    pub top_of_hour: bool,
+}
+
+impl JingleRules {
+    pub fn from_env() -> Self {
+        Self {
+            every_tracks: env_parse("JINGLE_EVERY_TRACKS", 0),
+            every_minutes: env_parse("JINGLE_EVERY_MINUTES", 0),
+            top_of_hour: env_parse("JINGLE_TOP_OF_HOUR", false),
+        }
+    }
+    
+    fn enabled(&self) -> bool {
+        self.every_tracks > 0 || self.every_minutes > 0 || self.top_of_hour
+    }
+}
+
+/// The local hour a time falls in, to detect crossing the top of an hour
+fn local_hour(time: DateTime<Utc>) -> (chrono::NaiveDate, u32) {
+    let local = time.with_timezone(&Local);
+    (local.date_naive(), local.hour())
+}
+
+/// Monitor-side state for deciding when the next jingle is due
+pub struct JingleScheduler {
+    rules: JingleRules,
+    tracks_since: u32,
+    last_jingle_at: DateTime<Utc>,
+    /// Expected end of the last track that was checked
+    covered_until: DateTime<Utc>,
+    last_pick: Option<String>,
+}
+
+impl JingleScheduler {
+    pub fn from_env() -> Self {
+        let now = Utc::now();
+        Self {
+            rules: JingleRules::from_env(),
+            tracks_since: 0,
+            last_jingle_at: now,
+            covered_until: now,
+            last_pick: None,
+        }
+    }
+    
+    /// Called when a song starts; queues a jingle right after it when one is due
+    ///
+    /// Rules are checked against the song's expected end, so a top-of-hour jingle plays at
+    /// the first track boundary after the hour. Returns whether a jingle was queued.
+    pub async fn on_song_started(&mut self, state: &AppState, client: &MpdClient, song: &SongInQueue) -> Result<bool, String> {
+        let now = Utc::now();
+        if is_jingle(&song.song.url) {
+            self.tracks_since = 0;
+            self.last_jingle_at = now;
+            return Ok(false);
+        }
+        if !self.rules.enabled() {
+            return Ok(false);
+        }
+        
+        self.tracks_since += 1;
+        let ends_at = now + song.song.duration
+            .and_then(|d| Duration::from_std(d).ok())
+            .unwrap_or_else(Duration::zero);
+        let crossed_hour = local_hour(ends_at) != local_hour(self.covered_until);
+        self.covered_until = ends_at;
+        
+        let due = (self.rules.every_tracks > 0 && self.tracks_since >= self.rules.every_tracks)
+            || (self.rules.every_minutes > 0 && ends_at - self.last_jingle_at >= Duration::minutes(self.rules.every_minutes as i64))
+            || (self.rules.top_of_hour && crossed_hour);
+        if !due {
+            return Ok(false);
+        }
+        
+        let queue = client
+            .command(commands::Queue)
+            .await
+            .map_err(|e| format!("Failed to get queue: {}", e))?;
+        let Some(position) = queue.iter().position(|s| s.id == song.id) else {
+            return Ok(false);
+        };
+        if queue.get(position + 1).is_some_and(|next| is_jingle(&next.song.url)) {
+            return Ok(false);
+        }
+        
+        let names = list_jingles(state).await.map_err(|e| format!("Failed to list jingles: {}", e))?;
+        // Avoid playing the same jingle twice in a row when there is a choice
+        let choices: Vec<&String> = names.iter()
+            .filter(|name| names.len() == 1 || Some(*name) != self.last_pick.as_ref())
+            .collect();
+        let Some(name) = choices.choose(&mut rand::thread_rng()).map(|name| name.to_string()) else {
+            return Ok(false);
+        };
+        
+        client
+            .command(commands::Add::uri(&jingle_path(&name)).at(SongPosition(position + 1)))
+            .await
+            .map_err(|e| format!("Failed to queue jingle {}: {}", name, e))?;
+        info!("Queued jingle {} after {}", name, song.song.url);
+        self.last_pick = Some(name);
+        Ok(true)
+    }
+}
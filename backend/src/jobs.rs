@@ -0,0 +1,135 @@
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::models::{UploadJob, UploadJobState};
+use crate::state::AppState;
+
+/// How long finished jobs stay queryable
+const FINISHED_JOB_RETENTION_MINUTES: i64 = 60;
+/// Bytes between two `upload_progress` events while receiving
+pub const PROGRESS_STEP_BYTES: u64 = 1024 * 1024;
+/// Shortest time between two `upload_progress` events for the same job
+const PROGRESS_MIN_INTERVAL_MS: i64 = 500;
+
+/// Uploads being received or processed, and recently finished ones
+#[derive(Default)]
+pub struct UploadJobs {
+    jobs: RwLock<HashMap<String, UploadJob>>,
+}
+
+impl UploadJobs {
+    async fn insert(&self, job: UploadJob) {
+        let mut jobs = self.jobs.write().await;
+        let cutoff = Utc::now() - Duration::minutes(FINISHED_JOB_RETENTION_MINUTES);
+        jobs.retain(|_, j| {
+            !matches!(j.state, UploadJobState::Queued | UploadJobState::Failed) || j.updated_at > cutoff
+        });
+        jobs.insert(job.job_id.clone(), job);
+    }
+    
+    pub async fn get(&self, job_id: &str) -> Option<UploadJob> {
+        self.jobs.read().await.get(job_id).cloned()
+    }
+    
+    /// A user's jobs, newest first
+    pub async fn for_user(&self, username: &str) -> Vec<UploadJob> {
+        let mut jobs: Vec<UploadJob> = self.jobs.read().await
+            .values()
+            .filter(|j| j.username == username)
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+        jobs
+    }
+    
+    async fn update<F: FnOnce(&mut UploadJob)>(&self, job_id: &str, f: F) -> Option<UploadJob> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.get_mut(job_id)?;
+        f(job);
+        job.updated_at = Utc::now();
+        Some(job.clone())
+    }
+    
+    /// Record received bytes; returns the job when enough time has passed to announce it
+    async fn progress(&self, job_id: &str, bytes_received: u64) -> Option<UploadJob> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.get_mut(job_id)?;
+        job.bytes_received = bytes_received;
+        let now = Utc::now();
+        if now - job.updated_at < Duration::milliseconds(PROGRESS_MIN_INTERVAL_MS) {
+            return None;
+        }
+        job.updated_at = now;
+        Some(job.clone())
+    }
+}
+
+fn progress_event(job: &UploadJob) -> String {
+    serde_json::json!({
+        "type": "upload_progress",
+        "data": job
+    }).to_string()
+}
+
+/// Uploads are private, so events only go to the uploader's own sessions
+async fn announce(state: &AppState, job: &UploadJob) {
+    state.send_to_user(&job.username, &progress_event(job)).await;
+}
+
+/// Register a job in the receiving state and announce it to its uploader; `job_id` is generated when not given
+pub async fn start_job(
+    state: &AppState,
+    job_id: Option<String>,
+    username: &str,
+    filename: &str,
+    total_bytes: Option<u64>,
+) -> String {
+    let now = Utc::now();
+    let job = UploadJob {
+        job_id: job_id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+        username: username.to_string(),
+        filename: filename.to_string(),
+        state: UploadJobState::Receiving,
+        bytes_received: 0,
+        total_bytes,
+        track_id: None,
+        deduplicated: false,
+        error: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let job_id = job.job_id.clone();
+    
+    state.upload_jobs.insert(job.clone()).await;
+    announce(state, &job).await;
+    job_id
+}
+
+/// Apply a change to a job and send it to the uploader as an `upload_progress` event
+pub async fn update_job<F: FnOnce(&mut UploadJob)>(state: &AppState, job_id: &str, f: F) {
+    if let Some(job) = state.upload_jobs.update(job_id, f).await {
+        announce(state, &job).await;
+    }
+}
+
+/// Record bytes received from inside a write loop
+/// Events are throttled and sent from a separate task so slow clients don't stall the upload
+pub async fn report_progress(state: &AppState, job_id: &str, bytes_received: u64) {
+    if let Some(job) = state.upload_jobs.progress(job_id, bytes_received).await {
+        let state = state.clone();
+        tokio::spawn(async move { announce(&state, &job).await });
+    }
+}
+
+pub async fn set_job_state(state: &AppState, job_id: &str, new_state: UploadJobState) {
+    update_job(state, job_id, |job| job.state = new_state).await;
+}
+
+pub async fn fail_job(state: &AppState, job_id: &str, error: &str) {
+    update_job(state, job_id, |job| {
+        job.state = UploadJobState::Failed;
+        job.error = Some(error.to_string());
+    }).await;
+}
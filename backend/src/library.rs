@@ -0,0 +1,312 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::sync::RwLock;
+
+use crate::models::{LibraryTrack, Track};
+use crate::playlist_formats::ImportEntry;
+use crate::state::AppState;
+
+/// Lowercase words of a piece of text, split on anything that isn't a letter or digit
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+fn track_tokens(track: &Track) -> HashSet<String> {
+    [&track.title, &track.artist, &track.album, &track.genre]
+        .into_iter()
+        .flatten()
+        .chain(std::iter::once(&track.added_by))
+        .flat_map(|text| tokenize(text))
+        .collect()
+}
+
+/// Inverted index over titles, artists, albums, genres and uploaders
+///
+/// Words are kept sorted so a query word also matches longer words it is a prefix of,
+/// which makes search-as-you-type work.
+#[derive(Default)]
+pub struct SearchIndex {
+    inner: RwLock<IndexInner>,
+}
+
+#[derive(Default)]
+struct IndexInner {
+    /// Word -> ids of the tracks containing it
+    words: BTreeMap<String, HashSet<String>>,
+    /// Track id -> its words, so a track can be unindexed
+    tracks: HashMap<String, HashSet<String>>,
+}
+
+impl IndexInner {
+    fn remove(&mut self, track_id: &str) {
+        let Some(words) = self.tracks.remove(track_id) else {
+            return;
+        };
+        for word in words {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(track_id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+}
+
+impl SearchIndex {
+    /// Index a track, replacing whatever was indexed for it before
+    pub async fn insert(&self, track: &Track) {
+        let words = track_tokens(track);
+        let mut inner = self.inner.write().await;
+        inner.remove(&track.id);
+        for word in &words {
+            inner.words.entry(word.clone()).or_default().insert(track.id.clone());
+        }
+        inner.tracks.insert(track.id.clone(), words);
+    }
+    
+    pub async fn remove(&self, track_id: &str) {
+        self.inner.write().await.remove(track_id);
+    }
+    
+    /// Ids of the tracks matching every word of the query, best matches first
+    /// A whole-word match scores higher than a prefix match
+    pub async fn search(&self, query: &str) -> Vec<(String, u32)> {
+        let inner = self.inner.read().await;
+        let mut scores: Option<HashMap<String, u32>> = None;
+        
+        for query_word in tokenize(query).collect::<HashSet<_>>() {
+            let mut matches: HashMap<String, u32> = HashMap::new();
+            for (word, ids) in inner.words.range(query_word.clone()..) {
+                if !word.starts_with(&query_word) {
+                    break;
+                }
+                let score = if *word == query_word { 2 } else { 1 };
+                for id in ids {
+                    let best = matches.entry(id.clone()).or_default();
+                    *best = (*best).max(score);
+                }
+            }
+            
+            scores = Some(match scores {
+                None => matches,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(id, score)| matches.get(&id).map(|s| (id, score + s)))
+                    .collect(),
+            });
+        }
+        
+        let mut results: Vec<(String, u32)> = scores.unwrap_or_default().into_iter().collect();
+        results.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        results
+    }
+}
+
+/// Field the library listing is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    AddedAt,
+    Artist,
+    Title,
+    Plays,
+}
+
+impl SortKey {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "added_at" => Some(SortKey::AddedAt),
+            "artist" => Some(SortKey::Artist),
+            "title" => Some(SortKey::Title),
+            "plays" => Some(SortKey::Plays),
+            _ => None,
+        }
+    }
+    
+    /// Newest and most played first; names alphabetically
+    pub fn descending_by_default(self) -> bool {
+        matches!(self, SortKey::AddedAt | SortKey::Plays)
+    }
+}
+
+/// Exact, case-insensitive field filters for the library listing
+#[derive(Debug, Default)]
+pub struct LibraryFilter {
+    pub uploader: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+}
+
+fn field_matches(filter: &Option<String>, value: Option<&str>) -> bool {
+    match filter {
+        None => true,
+        Some(filter) => value.is_some_and(|v| v.trim().eq_ignore_ascii_case(filter.trim())),
+    }
+}
+
+impl LibraryFilter {
+    fn matches(&self, track: &Track) -> bool {
+        field_matches(&self.uploader, Some(&track.added_by))
+            && field_matches(&self.artist, track.artist.as_deref())
+            && field_matches(&self.album, track.album.as_deref())
+            && field_matches(&self.genre, track.genre.as_deref())
+    }
+}
+
+/// Names compare case-insensitively, with untagged tracks last
+fn compare_names(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Catalog tracks matching the filter with their play statistics, sorted
+pub async fn library_tracks(
+    state: &AppState,
+    filter: &LibraryFilter,
+    sort: SortKey,
+    descending: bool,
+) -> Vec<LibraryTrack> {
+    let metadata = state.tracks_metadata.read().await;
+    let stats = state.track_stats.read().await;
+    
+    let mut tracks: Vec<LibraryTrack> = metadata
+        .values()
+        .filter(|track| filter.matches(track))
+        .map(|track| {
+            let track_stats = stats.get(&track.id).cloned().unwrap_or_default();
+            LibraryTrack {
+                track: track.clone(),
+                plays: track_stats.plays,
+                likes: track_stats.likes(),
+            }
+        })
+        .collect();
+    
+    tracks.sort_by(|a, b| {
+        let ordering = match sort {
+            SortKey::AddedAt => a.track.added_at.cmp(&b.track.added_at),
+            SortKey::Artist => compare_names(a.track.artist.as_deref(), b.track.artist.as_deref())
+                .then_with(|| compare_names(a.track.title.as_deref(), b.track.title.as_deref())),
+            SortKey::Title => compare_names(a.track.title.as_deref(), b.track.title.as_deref()),
+            SortKey::Plays => a.plays.cmp(&b.plays),
+        };
+        let ordering = if descending { ordering.reverse() } else { ordering };
+        // Keep pages stable between requests
+        ordering.then_with(|| a.track.id.cmp(&b.track.id))
+    });
+    tracks
+}
+
+/// Catalog tracks matching a search query, best matches first
+pub async fn search_tracks(state: &AppState, query: &str) -> Vec<LibraryTrack> {
+    let results = state.search_index.search(query).await;
+    let metadata = state.tracks_metadata.read().await;
+    let stats = state.track_stats.read().await;
+    
+    results
+        .into_iter()
+        .filter_map(|(id, _)| {
+            let track = metadata.get(&id)?.clone();
+            let track_stats = stats.get(&id).cloned().unwrap_or_default();
+            Some(LibraryTrack {
+                track,
+                plays: track_stats.plays,
+                likes: track_stats.likes(),
+            })
+        })
+        .collect()
+}
+
+/// Lowercase letters and digits with single spaces, for loose title comparison
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Dice coefficient over character bigrams, 1.0 for identical strings
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let bigrams = |s: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = s.chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect()
+    };
+    let (a, mut b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    
+    let total = a.len() + b.len();
+    let mut shared = 0;
+    for pair in &a {
+        if let Some(pos) = b.iter().position(|p| p == pair) {
+            b.swap_remove(pos);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / total as f64
+}
+
+/// How alike "artist title" has to be for a fuzzy match
+const FUZZY_MATCH_THRESHOLD: f64 = 0.85;
+/// Stricter bar when the entry only has a title
+const FUZZY_TITLE_ONLY_THRESHOLD: f64 = 0.95;
+
+/// Find the catalog track an imported playlist entry refers to: by path, then by
+/// file hash, then by approximate artist and title
+pub async fn match_import_entry(state: &AppState, entry: &ImportEntry) -> Option<Track> {
+    if let Some(path) = entry.path.as_deref() {
+        let basename = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        let metadata = state.tracks_metadata.read().await;
+        if let Some(track) = metadata.values().find(|t| t.filename == basename) {
+            return Some(track.clone());
+        }
+    }
+    
+    if let Some(hash) = entry.hash.as_deref().map(|h| h.trim().to_lowercase()) {
+        let stored = state.storage.find_by_hash(&hash).await;
+        let metadata = state.tracks_metadata.read().await;
+        let track = stored
+            .and_then(|file| metadata.get(&file.track_id))
+            .or_else(|| metadata.values().find(|t| t.upload_hash.as_deref() == Some(hash.as_str())));
+        if let Some(track) = track {
+            return Some(track.clone());
+        }
+    }
+    
+    let title = normalize(entry.title.as_deref()?);
+    if title.is_empty() {
+        return None;
+    }
+    let artist = entry.artist.as_deref().map(normalize).filter(|a| !a.is_empty());
+    let (wanted, threshold) = match &artist {
+        Some(artist) => (format!("{} {}", artist, title), FUZZY_MATCH_THRESHOLD),
+        None => (title, FUZZY_TITLE_ONLY_THRESHOLD),
+    };
+    
+    let metadata = state.tracks_metadata.read().await;
+    metadata.values()
+        .filter_map(|track| {
+            let track_title = normalize(track.title.as_deref()?);
+            let candidate = match (&artist, track.artist.as_deref()) {
+                (Some(_), Some(track_artist)) => format!("{} {}", normalize(track_artist), track_title),
+                (Some(_), None) => return None,
+                (None, _) => track_title,
+            };
+            let score = similarity(&wanted, &candidate);
+            (score >= threshold).then_some((score, track))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, track)| track.clone())
+}
@@ -0,0 +1,367 @@
+use chrono::{DateTime, Utc};
+use lofty::id3::v2::{Frame, FrameId, SyncTextContentType, SynchronizedTextFrame, TimestampFormat};
+use lofty::mpeg::MpegFile;
+use lofty::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+
+use crate::catalog::save_catalog;
+use crate::models::{CurrentTrack, PlaybackState, Track};
+use crate::state::AppState;
+
+const DEFAULT_LYRICS_DIR: &str = "data/lyrics";
+/// Largest `.lrc` file accepted next to an upload
+pub const MAX_LRC_SIZE: usize = 256 * 1024;
+
+/// Whether a track's lyrics carry timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsKind {
+    Plain,
+    Synced,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricLine {
+    /// Seconds into the file at which the line is sung
+    pub time: f64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lyrics {
+    pub kind: LyricsKind,
+    /// The lyrics without timestamps
+    pub text: String,
+    /// Timed lines in order, empty for plain lyrics
+    pub lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    fn synced(mut lines: Vec<LyricLine>) -> Self {
+        lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let text = lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n");
+        Self { kind: LyricsKind::Synced, text, lines }
+    }
+    
+    /// Index of the line being sung `position` seconds into the file
+    fn line_at(&self, position: f64) -> Option<usize> {
+        self.lines.iter().rposition(|line| line.time <= position)
+    }
+}
+
+/// Parse a `[mm:ss.xx]` timestamp without its brackets
+fn parse_timestamp(tag: &str) -> Option<f64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u32 = minutes.trim().parse().ok()?;
+    // Some tools write the hundredths after a second colon
+    let seconds: f64 = seconds.trim().replacen(':', ".", 1).parse().ok()?;
+    (seconds.is_finite() && seconds >= 0.0).then_some(minutes as f64 * 60.0 + seconds)
+}
+
+/// Drop enhanced-LRC word timings such as `<00:12.34>`
+fn strip_word_timings(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) if parse_timestamp(&rest[start + 1..start + end]).is_some() => {
+                out.push_str(&rest[..start]);
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                out.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out.trim().to_string()
+}
+
+/// Parse LRC text; text without any timestamps becomes plain lyrics
+/// Returns `None` when there is nothing to show
+pub fn parse_lrc(text: &str) -> Option<Lyrics> {
+    let mut lines = Vec::new();
+    let mut offset_ms = 0i64;
+    
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        while let Some(tag_end) = rest.strip_prefix('[').and_then(|r| r.find(']')) {
+            let tag = &rest[1..=tag_end];
+            if let Some(time) = parse_timestamp(tag) {
+                times.push(time);
+            } else if let Some(offset) = tag.strip_prefix("offset:") {
+                offset_ms = offset.trim().parse().unwrap_or(0);
+            }
+            rest = rest[tag_end + 2..].trim_start();
+        }
+        let line = strip_word_timings(rest);
+        lines.extend(times.into_iter().map(|time| LyricLine { time, text: line.clone() }));
+    }
+    
+    if !lines.is_empty() {
+        // A positive offset makes lines show up earlier
+        for line in &mut lines {
+            line.time = (line.time - offset_ms as f64 / 1000.0).max(0.0);
+        }
+        return Some(Lyrics::synced(lines));
+    }
+    
+    let text = text.trim();
+    (!text.is_empty()).then(|| Lyrics {
+        kind: LyricsKind::Plain,
+        text: text.to_string(),
+        lines: Vec::new(),
+    })
+}
+
+/// Lines of an ID3v2 SYLT frame with millisecond timestamps
+fn read_sylt(path: &Path) -> Option<Lyrics> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mpeg = MpegFile::read_from(&mut file, lofty::config::ParseOptions::new()).ok()?;
+    let sylt = FrameId::Valid(Cow::Borrowed("SYLT"));
+    
+    mpeg.id3v2()?.into_iter().find_map(|frame| {
+        let Frame::Binary(binary) = frame else {
+            return None;
+        };
+        if binary.id() != &sylt {
+            return None;
+        }
+        let sylt = SynchronizedTextFrame::parse(&binary.data, binary.flags()).ok()?;
+        if sylt.content_type != SyncTextContentType::Lyrics || sylt.timestamp_format != TimestampFormat::MS {
+            return None;
+        }
+        let lines: Vec<LyricLine> = sylt.content.into_iter()
+            .map(|(ms, text)| LyricLine { time: ms as f64 / 1000.0, text: text.trim().to_string() })
+            .filter(|line| !line.text.is_empty())
+            .collect();
+        (!lines.is_empty()).then(|| Lyrics::synced(lines))
+    })
+}
+
+/// Lyrics embedded in an audio file: an ID3v2 SYLT frame, or else the USLT, LYRICS or
+/// equivalent tag, which may itself hold LRC (blocking)
+pub fn read_embedded_lyrics(path: &Path) -> Option<Lyrics> {
+    let is_mp3 = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("mp3"));
+    if is_mp3 {
+        if let Some(lyrics) = read_sylt(path) {
+            return Some(lyrics);
+        }
+    }
+    
+    let tagged = match lofty::read_from_path(path) {
+        Ok(tagged) => tagged,
+        Err(e) => {
+            warn!("Failed to read lyrics from {:?}: {}", path, e);
+            return None;
+        }
+    };
+    tagged.tags().iter()
+        .find_map(|tag| tag.get_string(&ItemKey::Lyrics))
+        .and_then(parse_lrc)
+}
+
+/// Where lyrics are stored, one `{track_id}.json` file per track
+/// Environment variable: LYRICS_DIR
+fn lyrics_dir() -> PathBuf {
+    PathBuf::from(std::env::var("LYRICS_DIR").unwrap_or_else(|_| DEFAULT_LYRICS_DIR.to_string()))
+}
+
+fn lyrics_path(track_id: &str) -> PathBuf {
+    lyrics_dir().join(format!("{}.json", sanitize_filename::sanitize(track_id)))
+}
+
+pub async fn load_lyrics(track_id: &str) -> Option<Lyrics> {
+    let data = tokio::fs::read(lyrics_path(track_id)).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub async fn save_lyrics(track_id: &str, lyrics: &Lyrics) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(lyrics_dir()).await?;
+    let data = serde_json::to_vec(lyrics).map_err(std::io::Error::other)?;
+    let path = lyrics_path(track_id);
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, &path).await
+}
+
+pub async fn remove_lyrics(track_id: &str) {
+    let _ = tokio::fs::remove_file(lyrics_path(track_id)).await;
+}
+
+/// Store lyrics for a catalogued track, replacing any it had
+pub async fn attach_lyrics(state: &AppState, track_id: &str, lyrics: &Lyrics) -> Result<(), String> {
+    if !state.tracks_metadata.read().await.contains_key(track_id) {
+        return Err("Track not found".to_string());
+    }
+    save_lyrics(track_id, lyrics).await.map_err(|e| format!("Failed to save lyrics: {}", e))?;
+    
+    if let Some(track) = state.tracks_metadata.write().await.get_mut(track_id) {
+        track.lyrics = Some(lyrics.kind);
+    }
+    save_catalog(state).await;
+    Ok(())
+}
+
+/// Store the lyrics embedded in a catalogued track's file, if it has any
+pub async fn extract_lyrics(state: &AppState, track_id: &str, filename: &str) {
+    let path = state.storage.path(filename);
+    let Some(lyrics) = tokio::task::spawn_blocking(move || read_embedded_lyrics(&path)).await.ok().flatten() else {
+        return;
+    };
+    if let Err(e) = attach_lyrics(state, track_id, &lyrics).await {
+        warn!("Failed to store lyrics of {}: {}", filename, e);
+    }
+}
+
+/// Latest playback state for the lyrics follower
+pub struct LyricsFollower {
+    playing: watch::Sender<Option<CurrentTrack>>,
+}
+
+impl Default for LyricsFollower {
+    fn default() -> Self {
+        Self { playing: watch::channel(None).0 }
+    }
+}
+
+impl LyricsFollower {
+    pub fn update(&self, current: &CurrentTrack) {
+        self.playing.send_replace(Some(current.clone()));
+    }
+}
+
+/// The track on air with synced lyrics and the instant it started
+fn synced_track(current: Option<CurrentTrack>) -> Option<(Track, DateTime<Utc>)> {
+    let current = current?;
+    if current.state != PlaybackState::Playing {
+        return None;
+    }
+    let track = current.track.filter(|t| t.lyrics == Some(LyricsKind::Synced))?;
+    Some((track, current.started_at?))
+}
+
+/// Broadcast a `lyrics_line` event whenever the track on air reaches a new timed line
+///
+/// Playback updates come from the monitor every couple of seconds; between them the
+/// follower sleeps until the next line is due, so lines go out on time.
+pub async fn start_lyrics_follower(state: AppState) {
+    let mut updates = state.lyrics.playing.subscribe();
+    tokio::spawn(async move {
+        let mut loaded: Option<(String, Option<Lyrics>)> = None;
+        let mut last_sent: Option<(String, usize)> = None;
+        
+        loop {
+            let current = updates.borrow_and_update().clone();
+            let mut next_due = None;
+            
+            if let Some((track, started_at)) = synced_track(current) {
+                if loaded.as_ref().map(|(id, _)| id) != Some(&track.id) {
+                    loaded = Some((track.id.clone(), load_lyrics(&track.id).await));
+                }
+                if let Some((track_id, Some(lyrics))) = &loaded {
+                    let position = (Utc::now() - started_at).num_milliseconds() as f64 / 1000.0;
+                    let index = lyrics.line_at(position);
+                    let next = lyrics.lines.get(index.map_or(0, |i| i + 1));
+                    
+                    if let Some(index) = index {
+                        let sent = (track_id.clone(), index);
+                        if last_sent.as_ref() != Some(&sent) {
+                            let line = &lyrics.lines[index];
+                            let message = serde_json::json!({
+                                "type": "lyrics_line",
+                                "data": {
+                                    "track_id": track_id,
+                                    "index": index,
+                                    "time": line.time,
+                                    "text": line.text,
+                                    "next_time": next.map(|l| l.time)
+                                }
+                            });
+                            state.broadcast_message(&message.to_string()).await;
+                            last_sent = Some(sent);
+                        }
+                    }
+                    next_due = next.map(|l| std::time::Duration::from_secs_f64((l.time - position).max(0.0)));
+                }
+            }
+            
+            let changed = match next_due {
+                Some(wait) => tokio::select! {
+                    changed = updates.changed() => changed,
+                    _ = tokio::time::sleep(wait) => Ok(()),
+                },
+                None => updates.changed().await,
+            };
+            if changed.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn times(lyrics: &Lyrics) -> Vec<f64> {
+        lyrics.lines.iter().map(|line| line.time).collect()
+    }
+    
+    #[test]
+    fn timestamps_accept_both_hundredths_separators() {
+        assert_eq!(parse_timestamp("01:02.50"), Some(62.5));
+        assert_eq!(parse_timestamp("01:02:50"), Some(62.5));
+        assert_eq!(parse_timestamp(" 00:07 "), Some(7.0));
+    }
+    
+    #[test]
+    fn metadata_tags_are_not_timestamps() {
+        assert_eq!(parse_timestamp("ar:Some Artist"), None);
+        assert_eq!(parse_timestamp("length:03:20"), None);
+        assert_eq!(parse_timestamp("00:-1.0"), None);
+        
+        let lyrics = parse_lrc("[ti:Title]\n[ar:Artist]\n[00:01.00]First\n").unwrap();
+        assert_eq!(lyrics.kind, LyricsKind::Synced);
+        assert_eq!(lyrics.text, "First");
+        assert_eq!(times(&lyrics), vec![1.0]);
+    }
+    
+    #[test]
+    fn repeated_lines_get_one_entry_per_timestamp() {
+        let lyrics = parse_lrc("[00:10.00][00:30.00]Chorus\n[00:20.00]Verse").unwrap();
+        assert_eq!(times(&lyrics), vec![10.0, 20.0, 30.0]);
+        assert_eq!(lyrics.text, "Chorus\nVerse\nChorus");
+    }
+    
+    #[test]
+    fn offset_moves_lines_earlier() {
+        let lyrics = parse_lrc("[offset:+500]\n[00:00.20]Intro\n[00:02.00]Line").unwrap();
+        assert_eq!(times(&lyrics), vec![0.0, 1.5]);
+        
+        let lyrics = parse_lrc("[offset:-1000]\n[00:02.00]Line").unwrap();
+        assert_eq!(times(&lyrics), vec![3.0]);
+    }
+    
+    #[test]
+    fn word_timings_are_stripped() {
+        let lyrics = parse_lrc("[00:01.00]<00:01.00>Hello <00:01.50>world <b>").unwrap();
+        assert_eq!(lyrics.lines[0].text, "Hello world <b>");
+    }
+    
+    #[test]
+    fn text_without_timestamps_is_plain() {
+        let lyrics = parse_lrc("  Just words\nand more\n").unwrap();
+        assert_eq!(lyrics.kind, LyricsKind::Plain);
+        assert_eq!(lyrics.text, "Just words\nand more");
+        assert!(lyrics.lines.is_empty());
+        
+        assert!(parse_lrc(" \n\n").is_none());
+    }
+}
@@ -1,15 +1,45 @@
+mod analysis;
 mod api;
+mod audio;
+mod autodj;
+mod catalog;
+mod config;
+mod dedup;
+mod direct_play;
+mod eviction;
+mod history;
+mod jingles;
+mod jobs;
+mod library;
+mod lyrics;
 mod models;
 mod mpd_manager;
+mod playlist_formats;
+mod programming;
+mod quota;
+mod resumable;
+mod rotation;
+mod scheduler;
+mod settings;
 mod state;
+mod stats;
+mod storage;
 
 use actix_cors::Cors;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use log::info;
 use std::env;
 
-use crate::mpd_manager::start_mpd_monitor;
+use crate::analysis::start_analysis_worker;
+use crate::catalog::load_catalog;
+use crate::dedup::start_fingerprint_indexer;
+use crate::lyrics::start_lyrics_follower;
+use crate::mpd_manager::{apply_playback_settings, connect_mpd, start_mpd_connection_watcher, start_mpd_monitor};
+use crate::programming::start_programming_scheduler;
+use crate::resumable::start_resumable_upload_expiry;
 use crate::state::AppState;
+use crate::stats::load_track_stats;
+use crate::storage::start_storage_reconciler;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -28,22 +58,13 @@ async fn main() -> std::io::Result<()> {
     info!("Connecting to MPD at {}:{}", mpd_host, mpd_port);
     
     let mpd_addr = format!("{}:{}", mpd_host, mpd_port);
-    let connection = match tokio::net::TcpStream::connect(&mpd_addr).await {
-        Ok(conn) => conn,
-        Err(e) => {
-            eprintln!("Failed to connect to MPD at {}: {}", mpd_addr, e);
-            eprintln!("Make sure the MPD service is running and accessible at {}:{}", mpd_host, mpd_port);
-            std::process::exit(1);
-        }
-    };
-    
-    let (mpd_client, _events) = match mpd_client::Client::connect(connection).await {
-        Ok(client_tuple) => {
+    let (mpd_client, mpd_events) = match connect_mpd(&mpd_addr).await {
+        Ok(connection) => {
             info!("Successfully connected to MPD at {}:{}", mpd_host, mpd_port);
-            client_tuple
+            connection
         }
         Err(e) => {
-            eprintln!("Failed to connect to MPD at {}: {}", mpd_addr, e);
+            eprintln!("{}", e);
             eprintln!("Make sure the MPD service is running and accessible at {}:{}", mpd_host, mpd_port);
             std::process::exit(1);
         }
@@ -52,8 +73,27 @@ async fn main() -> std::io::Result<()> {
     // Create application state
     let app_state = web::Data::new(AppState::new(mpd_client));
     
+    // Index the uploads directory before accepting uploads
+    if let Err(e) = app_state.storage.reconcile().await {
+        eprintln!("Failed to index uploads directory: {}", e);
+        std::process::exit(1);
+    }
+    start_storage_reconciler(app_state.storage.clone()).await;
+    load_catalog(&app_state).await;
+    load_track_stats(&app_state).await;
+    app_state.history.load().await;
+    start_analysis_worker(app_state.get_ref().clone()).await;
+    start_fingerprint_indexer(app_state.get_ref().clone()).await;
+    start_resumable_upload_expiry(app_state.get_ref().clone()).await;
+    
+    app_state.settings.load().await;
+    apply_playback_settings(&app_state).await;
+    start_mpd_connection_watcher(app_state.get_ref().clone(), mpd_addr, mpd_events).await;
+    
     // Start MPD monitor
     start_mpd_monitor(app_state.get_ref().clone()).await;
+    start_lyrics_follower(app_state.get_ref().clone()).await;
+    start_programming_scheduler(app_state.get_ref().clone()).await;
     
     let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
     info!("Starting HTTP server on {}", bind_addr);
@@ -73,10 +113,53 @@ async fn main() -> std::io::Result<()> {
             .wrap(Logger::default())
             .wrap(cors)
             .service(api::upload::upload_music)
+            .service(api::upload::list_upload_jobs)
+            .service(api::upload::get_upload_job)
+            .service(api::resumable::create_upload)
+            .service(api::resumable::upload_status)
+            .service(api::resumable::append_chunk)
+            .service(api::resumable::finalize_upload)
+            .service(api::resumable::cancel_upload)
+            .service(api::quota::get_my_quota)
+            .service(api::storage::get_storage)
             .service(api::playlist::get_current)
             .service(api::playlist::get_queue_list)
             .service(api::playlist::add_to_queue)
             .service(api::playlist::play)
+            .service(api::export::export_queue)
+            .service(api::export::import_queue)
+            .service(api::export::get_history)
+            .service(api::export::export_history)
+            .service(api::saved_playlists::list_playlists)
+            .service(api::saved_playlists::create_playlist)
+            .service(api::saved_playlists::get_playlist)
+            .service(api::saved_playlists::rename_playlist)
+            .service(api::saved_playlists::delete_playlist)
+            .service(api::saved_playlists::add_playlist_track)
+            .service(api::saved_playlists::remove_playlist_track)
+            .service(api::saved_playlists::load_playlist)
+            .service(api::jingles::get_jingles)
+            .service(api::jingles::upload_jingles)
+            .service(api::jingles::delete_jingle)
+            .service(api::playback::pause)
+            .service(api::playback::resume)
+            .service(api::playback::stop)
+            .service(api::playback::seek)
+            .service(api::playback::previous)
+            .service(api::settings::get_settings)
+            .service(api::settings::update_settings)
+            .service(api::programming::list_shows)
+            .service(api::programming::create_show)
+            .service(api::programming::update_show)
+            .service(api::programming::delete_show)
+            .service(api::programming::current_show)
+            .service(api::tracks::list_tracks)
+            .service(api::tracks::search)
+            .service(api::tracks::like_track)
+            .service(api::tracks::unlike_track)
+            .service(api::tracks::get_waveform)
+            .service(api::tracks::get_track_file)
+            .service(api::tracks::get_lyrics)
             .service(api::stream::websocket)
             .service(api::stream::stream_proxy)
     })
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+
+use crate::lyrics::LyricsKind;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Track {
@@ -8,9 +11,78 @@ pub struct Track {
     pub title: Option<String>,
     pub artist: Option<String>,
     pub album: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
     pub duration: Option<f64>,
     pub added_by: String,
     pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    /// Seconds of leading silence skipped on air
+    #[serde(default)]
+    pub cue_in: Option<f64>,
+    /// Position in seconds where trailing silence starts
+    #[serde(default)]
+    pub cue_out: Option<f64>,
+    /// Version of the background analysis last run on this track, 0 if never
+    #[serde(default)]
+    pub analysis_version: u32,
+    /// Version of the waveform stored for this track, 0 if none
+    #[serde(default)]
+    pub waveform_version: u32,
+    /// SHA-256 of the file as uploaded, before analysis rewrote its tags
+    #[serde(default)]
+    pub upload_hash: Option<String>,
+    /// Whether lyrics are stored for the track, served at `/api/tracks/{id}/lyrics`
+    #[serde(default)]
+    pub lyrics: Option<LyricsKind>,
+    /// Track id of an earlier upload this one sounds like, left for an admin to review
+    #[serde(default)]
+    pub possible_duplicate_of: Option<String>,
+}
+
+/// EBU R128 measurement of a track and the ReplayGain values derived from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub true_peak_dbtp: f64,
+    /// ReplayGain 2.0 track gain, relative to a -18 LUFS reference
+    pub track_gain_db: f64,
+    /// Linear true peak, as written to REPLAYGAIN_TRACK_PEAK
+    pub track_peak: f64,
+}
+
+/// Play statistics used by eviction and ranking
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackStats {
+    pub plays: u32,
+    /// Users who liked the track; each user counts once
+    #[serde(default)]
+    pub liked_by: BTreeSet<String>,
+    pub last_played_at: Option<DateTime<Utc>>,
+}
+
+impl TrackStats {
+    pub fn likes(&self) -> u32 {
+        self.liked_by.len() as u32
+    }
+}
+
+/// A catalog track with its play statistics, as listed by the library API
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryTrack {
+    #[serde(flatten)]
+    pub track: Track,
+    pub plays: u32,
+    pub likes: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackPage {
+    pub tracks: Vec<LibraryTrack>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,8 +94,26 @@ pub struct QueueItem {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CurrentTrack {
     pub track: Option<Track>,
+    /// Seconds into the file when the status was sampled
     pub elapsed: Option<f64>,
     pub state: PlaybackState,
+    /// Server wall-clock time at which `elapsed` was sampled
+    pub server_time: DateTime<Utc>,
+    /// When the track would have started at normal speed; the position at any
+    /// server time `t` is `t - started_at`
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the track will finish if it keeps playing (its cue-out point when set)
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+/// Messages clients send over the WebSocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// NTP-style clock sync; `client_time` is the client's clock in milliseconds since the epoch
+    TimeSync { client_time: f64 },
+    /// Opt in to or out of direct playback of original files
+    DirectPlay { enabled: bool },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -39,9 +129,165 @@ pub struct UploadResponse {
     pub success: bool,
     pub track_id: String,
     pub filename: String,
+    /// True when the upload matched an existing track, which was queued instead
+    pub deduplicated: bool,
+    /// Track id of an existing upload that sounds the same; the new file was still kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub possible_duplicate_of: Option<String>,
+    pub job_id: String,
+    /// `.lrc` files sent alongside that could not be attached
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_lyrics: Vec<SkippedFile>,
+}
+
+/// A file of an upload that was left out, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub filename: String,
+    pub error: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AddToQueueRequest {
     pub track_id: String,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct SeekRequest {
+    /// Seconds from the start of the current track
+    pub position: f64,
+}
+
+/// A stored MPD playlist as listed by `GET /api/playlists`
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedPlaylistInfo {
+    pub name: String,
+    /// As reported by MPD (ISO 8601)
+    pub last_modified: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedPlaylist {
+    pub name: String,
+    pub tracks: Vec<Track>,
+}
+
+/// Body for creating or renaming a saved playlist
+#[derive(Debug, Deserialize)]
+pub struct PlaylistNameRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistLoadMode {
+    /// Add the playlist after everything already queued
+    #[default]
+    Append,
+    /// Drop the upcoming tracks that aren't pending requests, then add the playlist
+    Replace,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct LoadPlaylistRequest {
+    #[serde(default)]
+    pub mode: PlaylistLoadMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaAllowance {
+    pub used: u64,
+    pub limit: u64,
+    pub remaining: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaStatus {
+    pub username: String,
+    pub queued_tracks: QuotaAllowance,
+    pub storage_bytes: QuotaAllowance,
+    pub uploads_this_hour: QuotaAllowance,
+    pub next_upload_slot_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+    pub available_bytes: u64,
+    pub usage_percent: f64,
+    pub file_count: usize,
+    pub uploader_count: usize,
+    pub oldest_upload_at: Option<DateTime<Utc>>,
+    pub newest_upload_at: Option<DateTime<Utc>>,
+    pub last_reconciled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateResumableUpload {
+    pub filename: String,
+    pub size: u64,
+    /// Optional SHA-256 of the whole file, verified when the upload is finalized
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumableUploadStatus {
+    pub upload_id: String,
+    pub filename: String,
+    pub size: u64,
+    pub offset: u64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Outcome of one file in a multi-file upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchUploadItem {
+    pub filename: String,
+    pub job_id: String,
+    pub success: bool,
+    pub track_id: Option<String>,
+    pub stored_filename: Option<String>,
+    pub deduplicated: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchUploadResponse {
+    /// True when every file was uploaded
+    pub success: bool,
+    pub uploaded: usize,
+    pub failed: usize,
+    pub results: Vec<BatchUploadItem>,
+    /// `.lrc` files that could not be attached to any uploaded track
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_lyrics: Vec<SkippedFile>,
+}
+
+/// Processing stage of an upload job
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadJobState {
+    Receiving,
+    Validating,
+    ExtractingTags,
+    Indexing,
+    Queued,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadJob {
+    pub job_id: String,
+    pub username: String,
+    pub filename: String,
+    pub state: UploadJobState,
+    pub bytes_received: u64,
+    /// Declared size, when the client announced one
+    pub total_bytes: Option<u64>,
+    pub track_id: Option<String>,
+    pub deduplicated: bool,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -1,199 +1,360 @@
+use crate::autodj::{fill_queue, is_autodj_pick, AutoDjConfig};
+use crate::direct_play::sync_direct_play;
+use crate::jingles::{is_jingle, JingleScheduler};
 use crate::models::{CurrentTrack, PlaybackState, QueueItem, Track};
-use crate::state::AppState;
+use crate::quota::RequestRejected;
+use crate::rotation::{rotation_target, RotationConfig};
+use crate::scheduler::fair_order;
+use crate::state::{AppState, PendingRequest};
+use crate::stats::save_track_stats;
 use log::{error, info, warn};
 use mpd_client::commands;
-use mpd_client::commands::SongPosition;
-use mpd_client::responses::{PlayState, SongInQueue};
+use mpd_client::commands::{SongId, SongPosition};
+use mpd_client::client::{CommandError, ConnectionEvent, ConnectionEvents};
+use mpd_client::protocol::Command as RawCommand;
+use mpd_client::Client as MpdClient;
+use mpd_client::responses::{PlayState, Song, SongInQueue};
 use mpd_client::tag::Tag;
 use std::path::Path;
 
-/// Remove the last track from the MPD queue
-/// If delete_file is true, also deletes the file from disk
-pub async fn remove_last_track_from_queue(state: &AppState, delete_file: bool) -> Result<Option<String>, String> {
+/// How long to wait for MPD to index a new file
+const UPDATE_TIMEOUT_SECS: u64 = 30;
+const UPDATE_POLL_INTERVAL_MS: u64 = 50;
+/// Backoff between attempts to reconnect to MPD
+const RECONNECT_INITIAL_DELAY_SECS: u64 = 1;
+const RECONNECT_MAX_DELAY_SECS: u64 = 30;
+/// MPD's ACK code for a missing file or directory
+pub const ACK_ERROR_NO_EXIST: u64 = 50;
+
+/// Why a file couldn't be added to the MPD queue
+#[derive(Debug)]
+pub enum QueueError {
+    /// MPD finished indexing but has no such file (missing, unreadable or not audio)
+    NotInDatabase(String),
+    /// MPD didn't finish indexing the file in time
+    UpdateTimedOut(String),
+    /// Any other MPD failure
+    Mpd(String),
+    /// The request broke a request rule (already queued, cooldown, too many pending)
+    Rejected(RequestRejected),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::NotInDatabase(filename) => write!(f, "File not found in MPD database: {}", filename),
+            QueueError::UpdateTimedOut(filename) => write!(f, "Timed out waiting for MPD to index {}", filename),
+            QueueError::Mpd(message) => write!(f, "{}", message),
+            QueueError::Rejected(rejected) => write!(f, "{}", rejected.message()),
+        }
+    }
+}
+
+/// Index a single file and wait until MPD's update job for it has finished
+///
+/// The client lock is released between status polls so playback monitoring isn't held up.
+pub async fn update_database_path(state: &AppState, filename: &str) -> Result<(), QueueError> {
+    let job = {
+        let client = state.mpd_client.lock().await;
+        client
+            .command(commands::Update::new().uri(filename))
+            .await
+            .map_err(|e| QueueError::Mpd(format!("Failed to update MPD database: {}", e)))?
+    };
+    
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(UPDATE_TIMEOUT_SECS);
+    loop {
+        let status = {
+            let client = state.mpd_client.lock().await;
+            client
+                .command(commands::Status)
+                .await
+                .map_err(|e| QueueError::Mpd(format!("Failed to get status: {}", e)))?
+        };
+        
+        // MPD reports the running job; ours is done once it's gone or a later job runs
+        match status.update_job {
+            Some(running) if running <= job => {}
+            _ => return Ok(()),
+        }
+        
+        if tokio::time::Instant::now() >= deadline {
+            return Err(QueueError::UpdateTimedOut(filename.to_string()));
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(UPDATE_POLL_INTERVAL_MS)).await;
+    }
+}
+
+pub async fn add_file_to_mpd(state: &AppState, filename: &str, added_by: &str) -> Result<(), QueueError> {
+    queue_file(state, filename, added_by, |_| Ok(())).await
+}
+
+/// Queue `filename` as a request once `check` accepts the current pending requests
+///
+/// The pending requests stay locked from the check until the request is recorded, so
+/// concurrent requests can't both pass it. When the file already has a queue entry that
+/// isn't playing or pending (e.g. it is rotating through the queue), that entry becomes
+/// the request and moves up instead of a second copy being added.
+pub async fn queue_file(
+    state: &AppState,
+    filename: &str,
+    added_by: &str,
+    check: impl FnOnce(&[PendingRequest]) -> Result<(), RequestRejected>,
+) -> Result<(), QueueError> {
+    update_database_path(state, filename).await?;
+    
     let client = state.mpd_client.lock().await;
     
-    // Get the queue
+    let status = client
+        .command(commands::Status)
+        .await
+        .map_err(|e| QueueError::Mpd(format!("Failed to get status: {}", e)))?;
     let queue = client
         .command(commands::Queue)
         .await
-        .map_err(|e| format!("Failed to get queue: {}", e))?;
+        .map_err(|e| QueueError::Mpd(format!("Failed to get queue: {}", e)))?;
+    let current_id = status.current_song.map(|(_, id)| id);
+    let show_songs = state.programming.show_song_ids().await;
     
-    if let Some((last_pos, last_song)) = queue.iter().enumerate().last() {
-        let filename = last_song.song.url.to_string();
-        info!("Removing last track from queue: {} (position {})", filename, last_pos);
+    {
+        let mut pending = state.pending_requests.lock().await;
+        check(&pending).map_err(QueueError::Rejected)?;
         
-        // Remove the track from the queue
-        client
-            .command(commands::Delete::position(SongPosition(last_pos)))
-            .await
-            .map_err(|e| format!("Failed to remove track from queue: {}", e))?;
-        
-        // Also remove from metadata
-        let track_id = {
-            let file_stem = Path::new(&filename)
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or(&filename);
-            
-            file_stem
-                .split('_')
-                .next()
-                .unwrap_or(file_stem)
-                .to_string()
-        };
-        
-        {
-            let mut metadata = state.tracks_metadata.write().await;
-            metadata.remove(&track_id);
+        // Prefer a rotating copy over an unplayed auto-DJ pick, which would otherwise
+        // start rotating as well
+        let mut existing = Vec::new();
+        for song in queue.iter().filter(|s| {
+            s.song.url == filename
+                && Some(s.id) != current_id
+                && !show_songs.contains(&s.id)
+                && !pending.iter().any(|r| r.song_id == s.id)
+        }) {
+            existing.push((is_autodj_pick(state, song.id).await, song.id));
         }
+        existing.sort_by_key(|(autodj, _)| *autodj);
         
-        // Optionally delete the file from disk
-        if delete_file {
-            let file_path = Path::new("uploads").join(&filename);
-            if let Err(e) = std::fs::remove_file(&file_path) {
-                warn!("Failed to delete file {:?}: {}", file_path, e);
-            } else {
-                info!("Deleted file from disk: {:?}", file_path);
+        // Its final position is decided by the fairness scheduler below
+        let song_id = match existing.first() {
+            Some((_, song_id)) => {
+                info!("Promoted queued {} (id {}) to a request by {}", filename, song_id.0, added_by);
+                *song_id
             }
-        }
+            None => {
+                let song_id = client
+                    .command(commands::Add::uri(filename))
+                    .await
+                    .map_err(|e| match e {
+                        CommandError::ErrorResponse { error, .. } if error.code == ACK_ERROR_NO_EXIST => {
+                            QueueError::NotInDatabase(filename.to_string())
+                        }
+                        e => QueueError::Mpd(format!("Failed to add file to queue: {}", e)),
+                    })?;
+                info!("Added {} to queue (id {}) requested by {}", filename, song_id.0, added_by);
+                apply_cue_range(state, &client, song_id, filename).await;
+                song_id
+            }
+        };
         
-        // Notify clients of queue update
-        drop(client); // Release lock before async call
-        let queue_update = serde_json::json!({
-            "type": "queue_update",
-            "data": {}
+        state.queue_requesters.write().await.insert(song_id, added_by.to_string());
+        pending.push(PendingRequest {
+            song_id,
+            filename: filename.to_string(),
+            added_by: added_by.to_string(),
+            requested_at: chrono::Utc::now(),
         });
-        state.broadcast_message(&queue_update.to_string()).await;
-        
-        Ok(Some(filename))
-    } else {
-        warn!("Queue is empty, cannot remove last track");
-        Ok(None)
     }
+    
+    sync_queue_schedule(state, &client).await.map_err(QueueError::Mpd)?;
+    
+    // Auto-play if not already playing, unless an admin stopped playback
+    if status.state == PlayState::Stopped && !state.is_playback_held() {
+        client
+            .command(commands::Play::current())
+            .await
+            .map_err(|e| QueueError::Mpd(format!("Failed to start playback: {}", e)))?;
+        info!("Started playback");
+    }
+    
+    Ok(())
 }
 
-pub async fn add_file_to_mpd(state: &AppState, filename: &str) -> Result<(), String> {
+/// Restrict a queue entry to the track's cue points so leading and trailing silence
+/// never reach the stream
+pub async fn apply_cue_range(state: &AppState, client: &MpdClient, song_id: SongId, filename: &str) {
+    let track_id = track_id_from_filename(filename);
+    let (cue_in, cue_out) = match state.tracks_metadata.read().await.get(&track_id) {
+        Some(track) => (track.cue_in, track.cue_out),
+        None => return,
+    };
+    if cue_in.is_none() && cue_out.is_none() {
+        return;
+    }
+    
+    // Either side of the range may be left open
+    let bound = |secs: Option<f64>| secs.map(|s| format!("{:.3}", s)).unwrap_or_default();
+    let command = RawCommand::new("rangeid")
+        .argument(song_id)
+        .argument(format!("{}:{}", bound(cue_in), bound(cue_out)));
+    if let Err(e) = client.raw_command(command).await {
+        warn!("Failed to set cue range on {}: {}", filename, e);
+    }
+}
+
+/// Apply a track's cue points to the queue entries already pointing at it
+/// The current song can't be edited by MPD and plays untrimmed
+pub async fn apply_cue_points_to_queue(state: &AppState, filename: &str) {
     let client = state.mpd_client.lock().await;
+    let (status, queue) = match (client.command(commands::Status).await, client.command(commands::Queue).await) {
+        (Ok(status), Ok(queue)) => (status, queue),
+        _ => {
+            warn!("Failed to read queue while applying cue points to {}", filename);
+            return;
+        }
+    };
+    let current_id = status.current_song.map(|(_, id)| id);
     
-    client
-        .command(commands::Update::new())
+    for song in queue.iter().filter(|s| s.song.url == filename && Some(s.id) != current_id) {
+        apply_cue_range(state, &client, song.id, filename).await;
+    }
+}
+
+/// Send the stored playback settings (crossfade, MixRamp, replay gain, modes) to MPD
+pub async fn apply_playback_settings(state: &AppState) {
+    let settings = state.settings.playback().await;
+    let client = state.mpd_client.lock().await;
+    match settings.apply(&client).await {
+        Ok(()) => info!("Applied playback settings: {:?}", settings),
+        Err(e) => error!("Failed to apply playback settings: {}", e),
+    }
+}
+
+/// Open a client connection to MPD at `host:port`
+pub async fn connect_mpd(addr: &str) -> Result<(MpdClient, ConnectionEvents), String> {
+    let connection = tokio::net::TcpStream::connect(addr)
         .await
-        .map_err(|e| format!("Failed to update MPD database: {}", e))?;
-    
-    // Wait a bit for the database to update
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-    
-    // Get current status and current song to determine where to insert the track
+        .map_err(|e| format!("Failed to connect to MPD at {}: {}", addr, e))?;
+    MpdClient::connect(connection)
+        .await
+        .map_err(|e| format!("Failed to connect to MPD at {}: {}", addr, e))
+}
+
+/// Reconnect whenever the MPD connection drops, and reapply the playback settings
+pub async fn start_mpd_connection_watcher(state: AppState, addr: String, mut events: ConnectionEvents) {
+    tokio::spawn(async move {
+        loop {
+            // Subsystem events are not used; wait for the connection to end
+            while let Some(event) = events.next().await {
+                if let ConnectionEvent::ConnectionClosed(e) = event {
+                    warn!("MPD connection closed: {}", e);
+                    break;
+                }
+            }
+            warn!("Lost connection to MPD, reconnecting");
+            
+            let mut delay = RECONNECT_INITIAL_DELAY_SECS;
+            events = loop {
+                tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
+                match connect_mpd(&addr).await {
+                    Ok((client, events)) => {
+                        *state.mpd_client.lock().await = client;
+                        break events;
+                    }
+                    Err(e) => {
+                        warn!("{}; retrying in {}s", e, delay);
+                        delay = (delay * 2).min(RECONNECT_MAX_DELAY_SECS);
+                    }
+                }
+            };
+            
+            info!("Reconnected to MPD at {}", addr);
+            apply_playback_settings(&state).await;
+        }
+    });
+}
+
+/// Reorder the upcoming part of the MPD queue so pending requests follow the fairness schedule
+///
+/// Pending requests are placed right after the current song in the order computed by
+/// `scheduler::fair_order`; already-played tracks rotated to the back are left untouched.
+pub async fn sync_queue_schedule(state: &AppState, client: &MpdClient) -> Result<(), String> {
     let status = client
         .command(commands::Status)
         .await
         .map_err(|e| format!("Failed to get status: {}", e))?;
     
-    // Get the queue BEFORE adding to find current song position
-    let queue_before = client
+    let queue = client
         .command(commands::Queue)
         .await
         .map_err(|e| format!("Failed to get queue: {}", e))?;
     
-    // Get current song to find its position in the queue
-    let current_song = client.command(commands::CurrentSong).await.ok().flatten();
+    let current_id = status.current_song.map(|(_, id)| id);
     
-    // Determine the target position: right after current song, or 0 if nothing is playing
-    let target_position = if let Some(song) = current_song {
-        // Find the position of the current song in the queue
-        match queue_before.iter().position(|s| s.id == song.id) {
-            Some(current_pos) => {
-                info!("Current song at position {}, target position will be {}", current_pos, current_pos + 1);
-                // Insert right after the currently playing track
-                current_pos + 1
-            }
-            None => {
-                warn!("Current song not found in queue, inserting at position 0");
-                0
-            }
-        }
-    } else {
-        // Nothing is playing, insert at the beginning
-        info!("No current song, inserting at position 0");
-        0
+    let schedule = {
+        let mut pending = state.pending_requests.lock().await;
+        // Forget requests that were removed from the queue or are playing now
+        pending.retain(|r| Some(r.song_id) != current_id && queue.iter().any(|s| s.id == r.song_id));
+        
+        let last_played = state.last_played_by_user.read().await;
+        fair_order(&pending, &last_played)
     };
     
-    // Add the track to the queue (it will be added at the end initially)
-    client
-        .command(commands::Add::uri(filename))
-        .await
-        .map_err(|e| format!("Failed to add file to queue: {}", e))?;
-    
-    // Get the queue again to find the newly added track
-    let queue_after = client
-        .command(commands::Queue)
-        .await
-        .map_err(|e| format!("Failed to get queue: {}", e))?;
+    state.queue_requesters.write().await.retain(|id, _| queue.iter().any(|s| s.id == *id));
     
-    info!("Queue length before: {}, after: {}", queue_before.len(), queue_after.len());
+    // Mirror the queue locally so target positions can be computed without refetching
+    let mut order: Vec<SongId> = queue.iter().map(|s| s.id).collect();
+    // Jingles and tracks of the show on air play before requests
+    let show_songs = state.programming.show_song_ids().await;
+    let jingles: Vec<SongId> = queue.iter().filter(|s| is_jingle(&s.song.url)).map(|s| s.id).collect();
     
-    // Find the newly added track (it should be at the end)
-    if let Some((last_pos, last_song)) = queue_after.iter().enumerate().last() {
-        // Check if this is the track we just added by matching the filename
-        let song_filename = last_song.song.url.to_string();
-        if song_filename == filename {
-            let last_position = last_pos;
-            
-            info!("Found new track at position {}, target position: {}", last_position, target_position);
-            
-            // Only move if it's not already at the target position
-            // Ensure target position is within valid bounds (0 to queue length)
-            let valid_target = target_position.min(queue_after.len());
-            
-            if last_position != valid_target {
-                info!("Moving track from position {} to position {}", last_position, valid_target);
-                // Move the track to the target position using the song ID
-                client
-                    .command(
-                        commands::Move::id(last_song.id)
-                            .to_position(SongPosition(valid_target))
-                    )
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to move track: {}", e);
-                        format!("Failed to move track to next position: {}", e)
-                    })?;
-                
-                // Verify the move worked by checking the queue again
-                let queue_verify = client
-                    .command(commands::Queue)
-                    .await
-                    .map_err(|e| format!("Failed to verify queue after move: {}", e))?;
-                
-                if let Some((verify_pos, _)) = queue_verify.iter().enumerate().find(|(_, s)| s.id == last_song.id) {
-                    if verify_pos == valid_target {
-                        info!("Successfully moved track to position {} (next track)", valid_target);
-                    } else {
-                        warn!("Move command completed but track is at position {} instead of {}", verify_pos, valid_target);
-                    }
-                } else {
-                    warn!("Could not verify track position after move");
-                }
-            } else {
-                info!("Track already at target position {}", valid_target);
-            }
-        } else {
-            warn!("Could not find newly added track by filename: {}", filename);
+    for (offset, request) in schedule.iter().enumerate() {
+        let Some(from) = order.iter().position(|id| *id == request.song_id) else {
+            continue;
+        };
+        let id = order.remove(from);
+        let mut first_slot = current_id
+            .and_then(|current| order.iter().position(|id| *id == current))
+            .map(|pos| pos + 1)
+            .unwrap_or(0);
+        while order.get(first_slot).is_some_and(|id| show_songs.contains(id) || jingles.contains(id)) {
+            first_slot += 1;
+        }
+        let target = (first_slot + offset).min(order.len());
+        order.insert(target, id);
+        
+        if from != target {
+            client
+                .command(commands::Move::id(id).to_position(SongPosition(target)))
+                .await
+                .map_err(|e| format!("Failed to move track to scheduled position: {}", e))?;
+            info!("Scheduled {} (requested by {}) at position {}", request.filename, request.added_by, target);
         }
-    } else {
-        warn!("Queue is empty after adding track");
     }
     
-    // Auto-play if not already playing
-    if status.state == PlayState::Stopped {
-        client
-            .command(commands::Play::current())
-            .await
-            .map_err(|e| format!("Failed to start playback: {}", e))?;
-        info!("Started playback");
+    Ok(())
+}
+
+/// Record that a song started playing, for the fairness scheduler, play stats and history
+async fn mark_song_started(state: &AppState, song: &SongInQueue) {
+    if is_jingle(&song.song.url) {
+        return;
     }
+    let track = song_in_queue_to_track(song, state).await;
     
-    Ok(())
+    {
+        let mut last_played = state.last_played_by_user.write().await;
+        last_played.insert(track.added_by.clone(), chrono::Utc::now());
+    }
+    state.history.record(track).await;
+    
+    {
+        let mut stats = state.track_stats.write().await;
+        let track_stats = stats.entry(track_id_from_filename(&song.song.url)).or_default();
+        track_stats.plays += 1;
+        track_stats.last_played_at = Some(chrono::Utc::now());
+    }
+    // Saved in the background, like the history, so the monitor doesn't wait on the disk
+    let state = state.clone();
+    tokio::spawn(async move { save_track_stats(&state).await });
 }
 
 pub async fn get_current_track(state: &AppState) -> Result<CurrentTrack, String> {
@@ -210,18 +371,38 @@ pub async fn get_current_track(state: &AppState) -> Result<CurrentTrack, String>
         PlayState::Stopped => PlaybackState::Stopped,
     };
     
+    let server_time = chrono::Utc::now();
+    
     if let Some(song) = client.command(commands::CurrentSong).await.ok().flatten() {
         let track = song_in_queue_to_track(&song, state).await;
+        let elapsed = status.elapsed.map(|d| d.as_secs_f64());
+        let started_at = elapsed.map(|e| server_time - chrono::Duration::microseconds((e * 1e6) as i64));
+        // A paused or stopped track has no predictable end
+        let end_offset = track.cue_out
+            .or(status.duration.map(|d| d.as_secs_f64()))
+            .or(track.duration);
+        let ends_at = match (&playback_state, started_at, end_offset) {
+            (PlaybackState::Playing, Some(started_at), Some(end)) => {
+                Some(started_at + chrono::Duration::microseconds((end * 1e6) as i64))
+            }
+            _ => None,
+        };
         Ok(CurrentTrack {
             track: Some(track),
-            elapsed: status.elapsed.map(|d| d.as_secs_f64()),
+            elapsed,
             state: playback_state,
+            server_time,
+            started_at,
+            ends_at,
         })
     } else {
         Ok(CurrentTrack {
             track: None,
             elapsed: None,
             state: playback_state,
+            server_time,
+            started_at: None,
+            ends_at: None,
         })
     }
 }
@@ -254,8 +435,8 @@ pub async fn get_queue(state: &AppState) -> Result<Vec<QueueItem>, String> {
     let mut coming_up_index = 1u32;
     
     for (pos, song) in queue.into_iter().enumerate() {
-        // Only include tracks that come after the current position
-        if pos >= current_position {
+        // Only include tracks that come after the current position; jingles stay hidden
+        if pos >= current_position && !is_jingle(&song.song.url) {
             let track = song_in_queue_to_track(&song, state).await;
             items.push(QueueItem {
                 position: coming_up_index, // Re-index: 1 = next, 2 = after next, etc.
@@ -269,9 +450,35 @@ pub async fn get_queue(state: &AppState) -> Result<Vec<QueueItem>, String> {
 }
 
 
+/// Escape `%` and `_` in a username stored in a filename, since `_` separates the parts
+pub fn escape_username(username: &str) -> String {
+    username.replace('%', "%25").replace('_', "%5F")
+}
+
+fn unescape_username(escaped: &str) -> String {
+    let mut username = String::with_capacity(escaped.len());
+    let mut rest = escaped;
+    while let Some(pos) = rest.find('%') {
+        username.push_str(&rest[..pos]);
+        let code = rest.get(pos + 1..pos + 3);
+        match code {
+            Some("25") => username.push('%'),
+            Some(c) if c.eq_ignore_ascii_case("5F") => username.push('_'),
+            _ => {
+                username.push('%');
+                rest = &rest[pos + 1..];
+                continue;
+            }
+        }
+        rest = &rest[pos + 3..];
+    }
+    username.push_str(rest);
+    username
+}
+
 /// Extract username from filename
 /// Expected format: {uuid}_{username}_{original_filename}
-fn extract_username_from_filename(filename: &str) -> Option<String> {
+pub fn extract_username_from_filename(filename: &str) -> Option<String> {
     let file_stem = Path::new(filename)
         .file_stem()
         .and_then(|s| s.to_str())
@@ -284,7 +491,7 @@ fn extract_username_from_filename(filename: &str) -> Option<String> {
     if parts.len() >= 3 {
         let username = parts[1].trim();
         if !username.is_empty() {
-            return Some(username.to_string());
+            return Some(unescape_username(username));
         }
     }
     
@@ -293,7 +500,7 @@ fn extract_username_from_filename(filename: &str) -> Option<String> {
 
 /// Parse artist and title from filename
 /// Expected format: {uuid}_{username}_{Artist} - {Title}.mp3
-fn parse_metadata_from_filename(filename: &str) -> (Option<String>, Option<String>) {
+pub fn parse_metadata_from_filename(filename: &str) -> (Option<String>, Option<String>) {
     // Remove file extension
     let file_stem = Path::new(filename)
         .file_stem()
@@ -330,29 +537,46 @@ fn parse_metadata_from_filename(filename: &str) -> (Option<String>, Option<Strin
     }
 }
 
-async fn song_in_queue_to_track(song: &SongInQueue, state: &AppState) -> Track {
-    let filename = song.song.url.to_string();
-    let file_stem = Path::new(&filename)
+/// Extract the track ID (the UUID part) from a filename
+/// Expected format: {uuid}_{username}_{original_filename}
+pub fn track_id_from_filename(filename: &str) -> String {
+    let file_stem = Path::new(filename)
         .file_stem()
         .and_then(|s| s.to_str())
-        .unwrap_or(&filename);
+        .unwrap_or(filename);
     
-    // Extract the UUID part from the filename (format: {uuid}_{username}_{original_filename})
-    let track_id = file_stem
+    file_stem
         .split('_')
         .next()
         .unwrap_or(file_stem)
-        .to_string();
+        .to_string()
+}
+
+async fn song_in_queue_to_track(song: &SongInQueue, state: &AppState) -> Track {
+    // Whoever requested this queue entry gets the credit (a re-queued or deduplicated
+    // upload may have been requested by someone other than the original uploader)
+    let requester = state.queue_requesters.read().await.get(&song.id).cloned();
+    song_to_track(&song.song, state, requester).await
+}
+
+/// Catalog entry for an MPD song, falling back to its tags and filename
+pub async fn song_to_track(song: &Song, state: &AppState, requester: Option<String>) -> Track {
+    let filename = song.url.to_string();
+    let track_id = track_id_from_filename(&filename);
     
     // Try to get metadata from our stored data
     let metadata = state.tracks_metadata.read().await;
     if let Some(stored_track) = metadata.get(&track_id) {
-        return stored_track.clone();
+        let mut track = stored_track.clone();
+        if let Some(requester) = requester {
+            track.added_by = requester;
+        }
+        return track;
     }
     
     // Extract from MPD tags first
-    let mut title = song.song.tags.get(&Tag::Title).and_then(|t| t.first()).map(|s| s.to_string());
-    let mut artist = song.song.tags.get(&Tag::Artist).and_then(|t| t.first()).map(|s| s.to_string());
+    let mut title = song.tags.get(&Tag::Title).and_then(|t| t.first()).map(|s| s.to_string());
+    let mut artist = song.tags.get(&Tag::Artist).and_then(|t| t.first()).map(|s| s.to_string());
     
     // If metadata is missing, try to parse from filename
     if title.is_none() || artist.is_none() {
@@ -366,7 +590,8 @@ async fn song_in_queue_to_track(song: &SongInQueue, state: &AppState) -> Track {
     }
     
     // Extract uploader name from filename if not in metadata
-    let added_by = extract_username_from_filename(&filename)
+    let added_by = requester
+        .or_else(|| extract_username_from_filename(&filename))
         .unwrap_or_else(|| "Unknown".to_string());
     
     Track {
@@ -374,10 +599,19 @@ async fn song_in_queue_to_track(song: &SongInQueue, state: &AppState) -> Track {
         filename: filename.clone(),
         title,
         artist,
-        album: song.song.tags.get(&Tag::Album).and_then(|t| t.first()).map(|s| s.to_string()),
-        duration: song.song.duration.map(|d| d.as_secs_f64()),
+        album: song.tags.get(&Tag::Album).and_then(|t| t.first()).map(|s| s.to_string()),
+        genre: song.tags.get(&Tag::Genre).and_then(|t| t.first()).map(|s| s.to_string()),
+        duration: song.duration.map(|d| d.as_secs_f64()),
         added_by,
         added_at: chrono::Utc::now(),
+        loudness: None,
+        cue_in: None,
+        cue_out: None,
+        analysis_version: 0,
+        waveform_version: 0,
+        upload_hash: None,
+        lyrics: None,
+        possible_duplicate_of: None,
     }
 }
 
@@ -412,11 +646,128 @@ pub async fn start_playback(state: &AppState) -> Result<(), String> {
     Ok(())
 }
 
-pub async fn start_mpd_monitor(state: AppState) {
-    use crate::api::upload::{get_max_total_storage, get_uploads_directory_size};
+/// Pause or resume; pausing holds playback until an admin resumes it
+pub async fn set_paused(state: &AppState, paused: bool) -> Result<(), String> {
+    let client = state.mpd_client.lock().await;
+    let status = client
+        .command(commands::Status)
+        .await
+        .map_err(|e| format!("Failed to get status: {}", e))?;
+    
+    state.set_playback_held(paused);
+    match (paused, status.state) {
+        (true, PlayState::Playing) => client
+            .command(commands::SetPause(true))
+            .await
+            .map_err(|e| format!("Failed to pause playback: {}", e))?,
+        (false, PlayState::Paused | PlayState::Stopped) => client
+            .command(commands::Play::current())
+            .await
+            .map_err(|e| format!("Failed to resume playback: {}", e))?,
+        _ => return Ok(()),
+    }
+    info!("Playback {}", if paused { "paused" } else { "resumed" });
+    Ok(())
+}
+
+/// Stop playback and hold it until an admin resumes
+pub async fn stop_playback(state: &AppState) -> Result<(), String> {
+    state.set_playback_held(true);
+    let client = state.mpd_client.lock().await;
+    client
+        .command(commands::Stop)
+        .await
+        .map_err(|e| format!("Failed to stop playback: {}", e))?;
+    info!("Playback stopped");
+    Ok(())
+}
+
+/// Jump to a position in the current song
+pub async fn seek_current(state: &AppState, position: std::time::Duration) -> Result<(), String> {
+    let client = state.mpd_client.lock().await;
+    client
+        .command(commands::Seek(commands::SeekMode::Absolute(position)))
+        .await
+        .map_err(|e| format!("Failed to seek: {}", e))?;
+    info!("Seeked to {:.1}s", position.as_secs_f64());
+    Ok(())
+}
+
+/// Play the most recently played track again, right after the current one
+///
+/// Finished tracks are rotated away from the current song, so MPD's own `previous`
+/// would pick an arbitrary track. Returns false when the track is no longer queued.
+pub async fn play_previous(state: &AppState) -> Result<bool, String> {
+    let client = state.mpd_client.lock().await;
+    let status = client
+        .command(commands::Status)
+        .await
+        .map_err(|e| format!("Failed to get status: {}", e))?;
+    let queue = client
+        .command(commands::Queue)
+        .await
+        .map_err(|e| format!("Failed to get queue: {}", e))?;
+    
+    let current = status.current_song.and_then(|(pos, _)| queue.get(pos.0));
+    let current_url = current.map(|s| s.song.url.as_str());
+    // The newest history entry is usually the song on air
+    let Some(previous) = state.history.recent(2).await
+        .into_iter()
+        .find(|entry| Some(entry.track.filename.as_str()) != current_url) else {
+        return Ok(false);
+    };
+    let Some((from, song)) = queue.iter().enumerate()
+        .find(|(_, s)| s.song.url == previous.track.filename && current.is_none_or(|c| c.id != s.id)) else {
+        return Ok(false);
+    };
     
+    // Final position right after the current song, which shifts left if the track was before it
+    let target = match status.current_song {
+        Some((pos, _)) if from < pos.0 => pos.0,
+        Some((pos, _)) => pos.0 + 1,
+        None => 0,
+    };
+    if target != from {
+        client
+            .command(commands::Move::id(song.id).to_position(SongPosition(target)))
+            .await
+            .map_err(|e| format!("Failed to move previous track: {}", e))?;
+    }
+    client
+        .command(commands::Play::song(song.id))
+        .await
+        .map_err(|e| format!("Failed to play previous track: {}", e))?;
+    state.set_playback_held(false);
+    info!("Playing previous track {}", previous.track.filename);
+    Ok(true)
+}
+
+/// Send every client the playback state right away instead of on the next monitor tick
+pub async fn broadcast_current_track(state: &AppState) {
+    match get_current_track(state).await {
+        Ok(current) => publish_current_track(state, &current).await,
+        Err(e) => error!("Failed to get current track: {}", e),
+    }
+}
+
+/// Broadcast `current_track` and keep direct-play clients in step with it
+async fn publish_current_track(state: &AppState, current: &CurrentTrack) {
+    let message = serde_json::json!({
+        "type": "current_track",
+        "data": current
+    });
+    state.broadcast_message(&message.to_string()).await;
+    sync_direct_play(state, current).await;
+    state.lyrics.update(current);
+}
+
+pub async fn start_mpd_monitor(state: AppState) {
+    let autodj = AutoDjConfig::from_env();
+    let mut jingles = JingleScheduler::from_env();
+    let rotation = RotationConfig::from_env();
     tokio::spawn(async move {
-        let mut previous_track_filename: Option<String> = None;
+        // Queue entries are tracked by id; the same file can be queued more than once
+        let mut previous_song: Option<(SongId, String)> = None;
         
         loop {
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
@@ -426,50 +777,69 @@ pub async fn start_mpd_monitor(state: AppState) {
                     // Get the current song to detect changes
                     let client = state.mpd_client.lock().await;
                     let current_song = client.command(commands::CurrentSong).await.ok().flatten();
-                    let current_track_filename = current_song.as_ref()
-                        .map(|s| s.song.url.to_string());
+                    let current_entry = current_song.as_ref()
+                        .map(|s| (s.id, s.song.url.to_string()));
+                    
+                    // A new song started: credit its requester and refresh the fair schedule
+                    if current_entry.is_some() && current_entry != previous_song {
+                        if let Some(song) = current_song.as_ref() {
+                            mark_song_started(&state, song).await;
+                            if let Err(e) = jingles.on_song_started(&state, &client, song).await {
+                                error!("Failed to schedule jingle: {}", e);
+                            }
+                        }
+                        if let Err(e) = sync_queue_schedule(&state, &client).await {
+                            error!("Failed to sync queue schedule: {}", e);
+                        }
+                    }
+                    
+                    // Keep enough upcoming tracks queued to avoid dead air
+                    let mut filled = false;
+                    if current_entry.is_some() && current_entry != previous_song {
+                        match fill_queue(&state, &client, &autodj).await {
+                            Ok(added) => filled = !added.is_empty(),
+                            Err(e) => error!("Auto-DJ failed to fill the queue: {}", e),
+                        }
+                    }
                     
                     // Check if song has changed (track finished playing)
-                    if let (Some(prev_filename), Some(curr_filename)) = (previous_track_filename.as_ref(), current_track_filename.as_ref()) {
-                        if prev_filename != curr_filename {
-                            // Song has changed, move the previous song to the end
-                            info!("Song changed from {} to {}, moving previous track to end", prev_filename, curr_filename);
+                    let mut rotated = false;
+                    if let (Some((prev_id, prev_filename)), Some((curr_id, curr_filename))) = (previous_song.as_ref(), current_entry.as_ref()) {
+                        if prev_id != curr_id {
+                            // Song has changed, move the previous song back into the rotation
+                            // Storage pressure is handled by the eviction policy, not by the rotation
+                            info!("Song changed from {} to {}, rotating previous track", prev_filename, curr_filename);
                             
-                            // Get the queue to find the previous song by filename
+                            // Get the queue to find the previous song's entry
                             if let Ok(queue) = client.command(commands::Queue).await {
-                                if let Some((_, prev_song)) = queue.iter().enumerate().find(|(_, s)| s.song.url.to_string() == *prev_filename) {
-                                    let prev_song_id = prev_song.id;
-                                    // Check storage space to determine if we should keep the track in queue
-                                    let max_storage = get_max_total_storage();
-                                    let current_size = get_uploads_directory_size().unwrap_or(0);
-                                    
-                                    // Only move to end if there's remaining storage space
-                                    // If storage is full, the track will stay in its current position
-                                    if current_size < max_storage {
-                                        // Move the previous song to the end of the queue
-                                        let queue_len = queue.len();
-                                        let prev_pos_in_queue = queue.iter().position(|s| s.id == prev_song_id).unwrap_or(0);
-                                        if prev_pos_in_queue < queue_len - 1 {
-                                            // Only move if it's not already at the end
+                                if let Some(prev_pos_in_queue) = queue.iter().position(|s| s.id == *prev_id) {
+                                    // Jingles and auto-DJ picks are added fresh each time, and songs of
+                                    // an ended show leave with it; none of them are rotated
+                                    let remove = is_jingle(prev_filename)
+                                        || is_autodj_pick(&state, *prev_id).await
+                                        || state.programming.take_ended_show_song(*prev_id).await;
+                                    if remove {
+                                        if let Err(e) = client.command(commands::Delete::id(*prev_id)).await {
+                                            error!("Failed to remove played {}: {}", prev_filename, e);
+                                        } else {
+                                            state.queue_requesters.write().await.remove(prev_id);
+                                            rotated = true;
+                                        }
+                                    } else {
+                                        let current_id = current_song.as_ref().map(|s| s.id);
+                                        let target = rotation_target(&state, &rotation, &queue, prev_pos_in_queue, current_id).await;
+                                        // Only move if it's not already in place
+                                        if target != prev_pos_in_queue {
                                             if let Err(e) = client.command(
-                                                commands::Move::id(prev_song_id)
-                                                    .to_position(SongPosition(queue_len - 1))
+                                                commands::Move::id(*prev_id)
+                                                    .to_position(SongPosition(target))
                                             ).await {
-                                                error!("Failed to move completed track to end: {}", e);
+                                                error!("Failed to rotate completed track: {}", e);
                                             } else {
-                                                info!("Moved completed track to end of queue (storage: {}/{} bytes)", current_size, max_storage);
-                                                // Notify clients of queue update
-                                                let queue_update = serde_json::json!({
-                                                    "type": "queue_update",
-                                                    "data": {}
-                                                });
-                                                drop(client); // Release lock before async call
-                                                state.broadcast_message(&queue_update.to_string()).await;
-                                                continue; // Skip the rest of this iteration
+                                                info!("Moved completed track to position {}", target);
+                                                rotated = true;
                                             }
                                         }
-                                    } else {
-                                        info!("Storage full ({}/{} bytes), keeping track in current position", current_size, max_storage);
                                     }
                                 }
                             }
@@ -477,50 +847,67 @@ pub async fn start_mpd_monitor(state: AppState) {
                     }
                     
                     // Update previous track filename
-                    previous_track_filename = current_track_filename;
+                    previous_song = current_entry;
                     drop(client);
                     
-                    // Check if queue playback has ended (stopped state with no current track but queue has items)
-                    if current.state == PlaybackState::Stopped && current.track.is_none() {
-                        // Check if there are tracks in the queue and restart from the beginning
-                        {
+                    if rotated || filled {
+                        // Notify clients of queue update
+                        let queue_update = serde_json::json!({
+                            "type": "queue_update",
+                            "data": {}
+                        });
+                        state.broadcast_message(&queue_update.to_string()).await;
+                        continue; // Skip the rest of this iteration
+                    }
+                    
+                    // Queue playback has ended (or never started): play unplayed requests first,
+                    // then auto-DJ picks, and only loop the played queue when there is nothing new
+                    if current.state == PlaybackState::Stopped && current.track.is_none() && !state.is_playback_held() {
+                        let added = {
                             let client = state.mpd_client.lock().await;
-                            let queue = client
-                                .command(commands::Queue)
-                                .await;
+                            let added = fill_queue(&state, &client, &autodj).await.unwrap_or_else(|e| {
+                                error!("Auto-DJ failed to fill the queue: {}", e);
+                                Vec::new()
+                            });
                             
-                            match queue {
-                                Ok(queue_vec) => {
-                                    if !queue_vec.is_empty() {
-                                        // Play the first song (position 0)
-                                        if let Err(e) = client.command(commands::Play::song(SongPosition(0))).await {
-                                            error!("Failed to restart queue: {}", e);
-                                        } else {
-                                            info!("Queue playback ended, restarting from beginning");
-                                        }
+                            match client.command(commands::Queue).await {
+                                Ok(queue) => {
+                                    let first_request = {
+                                        let pending = state.pending_requests.lock().await;
+                                        queue.iter().find(|s| pending.iter().any(|r| r.song_id == s.id)).map(|s| s.id)
+                                    };
+                                    let result = match first_request.or_else(|| added.first().copied()) {
+                                        Some(id) => Some(client.command(commands::Play::song(id)).await),
+                                        None if !queue.is_empty() => Some(client.command(commands::Play::song(SongPosition(0))).await),
+                                        None => None,
+                                    };
+                                    match result {
+                                        Some(Ok(())) => info!("Queue playback ended, resuming playback"),
+                                        Some(Err(e)) => error!("Failed to resume playback: {}", e),
+                                        None => {}
                                     }
                                 }
                                 Err(e) => {
                                     error!("Failed to get queue: {}", e);
                                 }
                             }
+                            added
+                        };
+                        if !added.is_empty() {
+                            let queue_update = serde_json::json!({
+                                "type": "queue_update",
+                                "data": {}
+                            });
+                            state.broadcast_message(&queue_update.to_string()).await;
                         }
                         // Get updated current track after restart
                         if let Ok(updated_current) = get_current_track(&state).await {
-                            let message = serde_json::json!({
-                                "type": "current_track",
-                                "data": updated_current
-                            });
-                            state.broadcast_message(&message.to_string()).await;
+                            publish_current_track(&state, &updated_current).await;
                             continue;
                         }
                     }
                     
-                    let message = serde_json::json!({
-                        "type": "current_track",
-                        "data": current
-                    });
-                    state.broadcast_message(&message.to_string()).await;
+                    publish_current_track(&state, &current).await;
                 }
                 Err(e) => {
                     error!("Failed to get current track: {}", e);
@@ -530,3 +917,22 @@ pub async fn start_mpd_monitor(state: AppState) {
     });
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn username_with_underscores_round_trips() {
+        for username in ["dj_bob", "a__b_", "100%_real", "%5F", "plain"] {
+            let filename = format!("uuid_{}_song_title.mp3", escape_username(username));
+            assert_eq!(extract_username_from_filename(&filename).as_deref(), Some(username));
+        }
+    }
+    
+    #[test]
+    fn unescape_keeps_stray_percent_signs() {
+        assert_eq!(unescape_username("50%"), "50%");
+        assert_eq!(unescape_username("%zz%5f"), "%zz_");
+    }
+}
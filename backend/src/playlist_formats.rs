@@ -0,0 +1,373 @@
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::events::Event;
+use quick_xml::escape::escape;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Track;
+
+/// Characters escaped in XSPF locations; `/` is kept so paths stay readable
+const LOCATION_ESCAPE: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?')
+    .add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+/// Prefix of XSPF identifiers carrying a file's SHA-256
+const HASH_URN_PREFIX: &str = "urn:sha256:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+    Json,
+}
+
+impl PlaylistFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "m3u8" | "m3u" => Some(PlaylistFormat::M3u8),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            "json" => Some(PlaylistFormat::Json),
+            _ => None,
+        }
+    }
+    
+    /// Guess the format of an uploaded playlist from its content
+    pub fn detect(body: &str) -> Self {
+        let start = body.trim_start();
+        if start.starts_with('[') || start.starts_with('{') {
+            PlaylistFormat::Json
+        } else if start.starts_with("<?xml") || start.contains("<playlist") {
+            PlaylistFormat::Xspf
+        } else {
+            PlaylistFormat::M3u8
+        }
+    }
+    
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "audio/x-mpegurl; charset=utf-8",
+            PlaylistFormat::Xspf => "application/xspf+xml",
+            PlaylistFormat::Json => "application/json",
+        }
+    }
+    
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Json => "json",
+        }
+    }
+}
+
+/// One exported queue or history entry
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportEntry {
+    pub track_id: String,
+    /// Path relative to the music directory
+    pub path: String,
+    /// SHA-256 of the file as stored
+    pub hash: Option<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<f64>,
+    pub added_by: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub played_at: Option<DateTime<Utc>>,
+}
+
+impl ExportEntry {
+    pub fn new(track: &Track, hash: Option<String>, played_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            track_id: track.id.clone(),
+            path: track.filename.clone(),
+            hash,
+            artist: track.artist.clone(),
+            title: track.title.clone(),
+            album: track.album.clone(),
+            duration: track.duration,
+            added_by: track.added_by.clone(),
+            played_at,
+        }
+    }
+    
+    fn display_name(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => self.path.clone(),
+        }
+    }
+}
+
+/// A playlist entry to look up in the catalog; any field may be missing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportEntry {
+    #[serde(default, alias = "filename", alias = "location")]
+    pub path: Option<String>,
+    #[serde(default, alias = "sha256")]
+    pub hash: Option<String>,
+    #[serde(default, alias = "creator")]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl ImportEntry {
+    fn is_empty(&self) -> bool {
+        self.path.is_none() && self.hash.is_none() && self.title.is_none()
+    }
+}
+
+pub fn render(entries: &[ExportEntry], format: PlaylistFormat, title: &str) -> String {
+    match format {
+        PlaylistFormat::M3u8 => render_m3u8(entries, title),
+        PlaylistFormat::Xspf => render_xspf(entries, title),
+        PlaylistFormat::Json => serde_json::json!({
+            "title": title,
+            "tracks": entries
+        }).to_string(),
+    }
+}
+
+/// M3U is line based, so line breaks inside a value would start a bogus entry
+fn m3u_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+fn render_m3u8(entries: &[ExportEntry], title: &str) -> String {
+    let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", m3u_value(title));
+    for entry in entries {
+        let duration = entry.duration.map(|d| d.round() as i64).unwrap_or(-1);
+        out.push_str(&format!("#EXTINF:{},{}\n", duration, m3u_value(&entry.display_name())));
+        if let Some(album) = &entry.album {
+            out.push_str(&format!("#EXTALB:{}\n", m3u_value(album)));
+        }
+        out.push_str(&format!("# Added by: {}\n", m3u_value(&entry.added_by)));
+        out.push_str(&m3u_value(&entry.path));
+        out.push('\n');
+    }
+    out
+}
+
+fn render_xspf(entries: &[ExportEntry], title: &str) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str(&format!("  <title>{}</title>\n  <trackList>\n", escape(title)));
+    for entry in entries {
+        out.push_str("    <track>\n");
+        let location = utf8_percent_encode(&entry.path, LOCATION_ESCAPE).to_string();
+        out.push_str(&format!("      <location>{}</location>\n", escape(&location)));
+        if let Some(hash) = &entry.hash {
+            out.push_str(&format!("      <identifier>{}{}</identifier>\n", HASH_URN_PREFIX, escape(hash)));
+        }
+        if let Some(title) = &entry.title {
+            out.push_str(&format!("      <title>{}</title>\n", escape(title)));
+        }
+        if let Some(artist) = &entry.artist {
+            out.push_str(&format!("      <creator>{}</creator>\n", escape(artist)));
+        }
+        if let Some(album) = &entry.album {
+            out.push_str(&format!("      <album>{}</album>\n", escape(album)));
+        }
+        if let Some(duration) = entry.duration {
+            out.push_str(&format!("      <duration>{}</duration>\n", (duration * 1000.0).round() as u64));
+        }
+        out.push_str(&format!("      <annotation>Added by {}</annotation>\n", escape(&entry.added_by)));
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+pub fn parse(body: &str, format: PlaylistFormat) -> Result<Vec<ImportEntry>, String> {
+    let entries = match format {
+        PlaylistFormat::M3u8 => parse_m3u8(body),
+        PlaylistFormat::Xspf => parse_xspf(body)?,
+        PlaylistFormat::Json => parse_json(body)?,
+    };
+    Ok(entries.into_iter().filter(|e| !e.is_empty()).collect())
+}
+
+/// Paths in playlists are often URLs; matching only cares about the decoded path
+fn decode_location(location: &str) -> String {
+    let location = location.trim();
+    let location = location.strip_prefix("file://").unwrap_or(location);
+    percent_decode_str(location).decode_utf8_lossy().into_owned()
+}
+
+fn parse_m3u8(body: &str) -> Vec<ImportEntry> {
+    let mut entries = Vec::new();
+    let mut pending = ImportEntry::default();
+    
+    for line in body.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // `#EXTINF:<duration> [attributes],<artist> - <title>`
+            let name = info.split_once(',').map(|(_, name)| name.trim()).unwrap_or("");
+            match name.split_once(" - ") {
+                Some((artist, title)) => {
+                    pending.artist = Some(artist.trim().to_string());
+                    pending.title = Some(title.trim().to_string());
+                }
+                None if !name.is_empty() => pending.title = Some(name.to_string()),
+                None => {}
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            pending.path = Some(decode_location(line));
+            entries.push(std::mem::take(&mut pending));
+        }
+    }
+    entries
+}
+
+fn parse_xspf(body: &str) -> Result<Vec<ImportEntry>, String> {
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+    
+    let mut entries = Vec::new();
+    let mut current: Option<ImportEntry> = None;
+    let mut field: Option<String> = None;
+    
+    loop {
+        match reader.read_event().map_err(|e| format!("Invalid XSPF: {}", e))? {
+            Event::Start(tag) => {
+                let name = String::from_utf8_lossy(tag.local_name().as_ref()).into_owned();
+                if name == "track" {
+                    current = Some(ImportEntry::default());
+                } else if current.is_some() {
+                    field = Some(name);
+                }
+            }
+            Event::Text(text) => {
+                let (Some(entry), Some(field)) = (current.as_mut(), field.as_deref()) else {
+                    continue;
+                };
+                let value = text.unescape().map_err(|e| format!("Invalid XSPF: {}", e))?.into_owned();
+                match field {
+                    "location" => entry.path = Some(decode_location(&value)),
+                    "identifier" => {
+                        if let Some(hash) = value.strip_prefix(HASH_URN_PREFIX) {
+                            entry.hash = Some(hash.to_string());
+                        }
+                    }
+                    "title" => entry.title = Some(value),
+                    "creator" => entry.artist = Some(value),
+                    _ => {}
+                }
+            }
+            Event::End(tag) => {
+                if tag.local_name().as_ref() == b"track" {
+                    entries.extend(current.take());
+                }
+                field = None;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_json(body: &str) -> Result<Vec<ImportEntry>, String> {
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|e| format!("Invalid JSON: {}", e))?;
+    // Either a bare list or an export document with a `tracks` list
+    let list = match value {
+        serde_json::Value::Object(mut object) => object.remove("tracks").unwrap_or_default(),
+        value => value,
+    };
+    let mut entries: Vec<ImportEntry> = serde_json::from_value(list).map_err(|e| format!("Invalid JSON playlist: {}", e))?;
+    for entry in &mut entries {
+        entry.path = entry.path.as_deref().map(decode_location);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn entry(path: &str, artist: Option<&str>, title: Option<&str>) -> ExportEntry {
+        ExportEntry {
+            track_id: "id".to_string(),
+            path: path.to_string(),
+            hash: Some("abc123".to_string()),
+            artist: artist.map(str::to_string),
+            title: title.map(str::to_string),
+            album: Some("Album".to_string()),
+            duration: Some(181.4),
+            added_by: "alice".to_string(),
+            played_at: None,
+        }
+    }
+    
+    fn entries() -> Vec<ExportEntry> {
+        vec![
+            entry("1_alice_Song A.mp3", Some("Artist & Co"), Some("Song <A>")),
+            entry("2_bob_100% #1?.flac", None, Some("Only a title")),
+            entry("3_carol_untagged.ogg", None, None),
+        ]
+    }
+    
+    fn round_trip(format: PlaylistFormat) -> Vec<ImportEntry> {
+        let body = render(&entries(), format, "Export");
+        assert_eq!(PlaylistFormat::detect(&body), format);
+        parse(&body, format).unwrap()
+    }
+    
+    fn paths(parsed: &[ImportEntry]) -> Vec<&str> {
+        parsed.iter().map(|e| e.path.as_deref().unwrap()).collect()
+    }
+    
+    #[test]
+    fn m3u8_round_trips_paths_and_names() {
+        let parsed = round_trip(PlaylistFormat::M3u8);
+        assert_eq!(paths(&parsed), vec!["1_alice_Song A.mp3", "2_bob_100% #1?.flac", "3_carol_untagged.ogg"]);
+        assert_eq!(parsed[0].artist.as_deref(), Some("Artist & Co"));
+        assert_eq!(parsed[0].title.as_deref(), Some("Song <A>"));
+        assert_eq!(parsed[1].artist, None);
+        assert_eq!(parsed[1].title.as_deref(), Some("Only a title"));
+    }
+    
+    #[test]
+    fn xspf_round_trips_escaped_fields_and_hashes() {
+        let parsed = round_trip(PlaylistFormat::Xspf);
+        assert_eq!(paths(&parsed), vec!["1_alice_Song A.mp3", "2_bob_100% #1?.flac", "3_carol_untagged.ogg"]);
+        assert_eq!(parsed[0].artist.as_deref(), Some("Artist & Co"));
+        assert_eq!(parsed[0].title.as_deref(), Some("Song <A>"));
+        assert!(parsed.iter().all(|e| e.hash.as_deref() == Some("abc123")));
+    }
+    
+    #[test]
+    fn json_round_trips_every_entry() {
+        let parsed = round_trip(PlaylistFormat::Json);
+        assert_eq!(paths(&parsed), vec!["1_alice_Song A.mp3", "2_bob_100% #1?.flac", "3_carol_untagged.ogg"]);
+        assert_eq!(parsed[2].title, None);
+        assert_eq!(parsed[0].hash.as_deref(), Some("abc123"));
+    }
+    
+    #[test]
+    fn line_breaks_in_tags_stay_inside_their_m3u_line() {
+        let mut tricky = entry("1_alice_a.mp3", Some("Evil\r\nArtist"), Some("Title\nx.mp3"));
+        tricky.album = Some("Album\n/etc/passwd".to_string());
+        tricky.added_by = "mallory\rnext".to_string();
+        let body = render(&[tricky], PlaylistFormat::M3u8, "List\nname");
+        
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().all(|line| line.starts_with('#') || *line == "1_alice_a.mp3"));
+        assert_eq!(paths(&parse(&body, PlaylistFormat::M3u8).unwrap()), vec!["1_alice_a.mp3"]);
+    }
+    
+    #[test]
+    fn m3u8_from_other_players_is_read() {
+        let body = "#EXTM3U\n\n#EXTINF:200 tvg-id=\"x\",Band - Tune\nfile:///music/My%20Tune.mp3\n# comment\nplain.flac\n";
+        let parsed = parse(body, PlaylistFormat::M3u8).unwrap();
+        assert_eq!(paths(&parsed), vec!["/music/My Tune.mp3", "plain.flac"]);
+        assert_eq!(parsed[0].artist.as_deref(), Some("Band"));
+        assert_eq!(parsed[1].title, None);
+    }
+}
@@ -0,0 +1,556 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, Timelike, Utc, Weekday};
+use log::{error, info, warn};
+use mpd_client::commands;
+use mpd_client::commands::{SongId, SongPosition};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::library::{library_tracks, LibraryFilter, SortKey};
+use crate::mpd_manager::{apply_cue_range, sync_queue_schedule};
+use crate::state::AppState;
+
+const DEFAULT_SCHEDULE_PATH: &str = "data/schedule.json";
+const SCHEDULE_CHECK_INTERVAL_SECS: u64 = 15;
+const MINUTES_PER_WEEK: i64 = 7 * 24 * 60;
+/// Upper bound on tracks queued for one show
+const MAX_SHOW_TRACKS: usize = 200;
+/// Assumed length of tracks with an unknown duration when filling a slot
+const FALLBACK_TRACK_SECS: f64 = 180.0;
+
+/// Why a show couldn't be created, changed or removed
+#[derive(Debug)]
+pub enum ShowError {
+    /// The show failed validation
+    Invalid(String),
+    /// The slot overlaps the named enabled show
+    Overlap(String),
+    /// The schedule couldn't be saved
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ShowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShowError::Invalid(message) => write!(f, "{}", message),
+            ShowError::Overlap(name) => write!(f, "Overlaps with show \"{}\"", name),
+            ShowError::Io(e) => write!(f, "Failed to save schedule: {}", e),
+        }
+    }
+}
+
+/// What a show plays
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShowSource {
+    /// A saved MPD playlist, in order
+    Playlist { name: String },
+    /// Library tracks matching the given tags, shuffled
+    Filter {
+        #[serde(default)]
+        genre: Option<String>,
+        #[serde(default)]
+        artist: Option<String>,
+        #[serde(default)]
+        album: Option<String>,
+    },
+    /// Tracks uploaded by the host, shuffled
+    Host { username: String },
+}
+
+/// Fields of a show that admins can set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShowInput {
+    pub name: String,
+    /// Days the slot recurs on, e.g. `["Fri"]`
+    pub days: Vec<Weekday>,
+    /// Local start time, `HH:MM`
+    pub start: String,
+    pub duration_minutes: u32,
+    pub source: ShowSource,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A recurring weekly programming slot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Show {
+    pub id: String,
+    #[serde(flatten)]
+    pub input: ShowInput,
+}
+
+/// The show on air and the queue entries it added
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveShow {
+    pub show: Show,
+    pub started_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub song_ids: HashSet<SongId>,
+}
+
+impl ShowInput {
+    fn start_minute(&self) -> Option<i64> {
+        let start = NaiveTime::parse_from_str(self.start.trim(), "%H:%M").ok()?;
+        Some((start.hour() * 60 + start.minute()) as i64)
+    }
+    
+    /// Start of each weekly occurrence, in minutes since Monday 00:00
+    fn occurrences(&self) -> Vec<i64> {
+        let Some(start) = self.start_minute() else {
+            return Vec::new();
+        };
+        self.days.iter()
+            .map(|day| day.num_days_from_monday() as i64 * 24 * 60 + start)
+            .collect()
+    }
+    
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Show name is required".to_string());
+        }
+        if self.days.is_empty() {
+            return Err("At least one day is required".to_string());
+        }
+        if self.start_minute().is_none() {
+            return Err("Invalid start time, expected HH:MM".to_string());
+        }
+        if self.duration_minutes == 0 || self.duration_minutes > 24 * 60 {
+            return Err("Duration must be between 1 and 1440 minutes".to_string());
+        }
+        match &self.source {
+            ShowSource::Playlist { name } if name.trim().is_empty() => Err("Playlist name is required".to_string()),
+            ShowSource::Host { username } if username.trim().is_empty() => Err("Host username is required".to_string()),
+            ShowSource::Filter { genre: None, artist: None, album: None } => {
+                Err("A tag filter needs at least one of genre, artist or album".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    fn overlaps(&self, other: &ShowInput) -> bool {
+        let (a_len, b_len) = (self.duration_minutes as i64, other.duration_minutes as i64);
+        self.occurrences().iter().any(|a| {
+            other.occurrences().iter().any(|b| {
+                // Slots repeat weekly, so compare on a circle
+                let offset = (b - a).rem_euclid(MINUTES_PER_WEEK);
+                offset < a_len || MINUTES_PER_WEEK - offset < b_len
+            })
+        })
+    }
+    
+    /// Minutes left in the occurrence running at `minute_of_week`, if any
+    fn remaining_at(&self, minute_of_week: i64) -> Option<i64> {
+        let duration = self.duration_minutes as i64;
+        self.occurrences().iter()
+            .map(|start| (minute_of_week - start).rem_euclid(MINUTES_PER_WEEK))
+            .filter(|elapsed| *elapsed < duration)
+            .map(|elapsed| duration - elapsed)
+            .max()
+    }
+}
+
+fn minute_of_week(now: DateTime<Local>) -> i64 {
+    now.weekday().num_days_from_monday() as i64 * 24 * 60 + (now.hour() * 60 + now.minute()) as i64
+}
+
+/// Weekly programming grid, persisted as JSON
+/// Slot times are in the server's local time zone (TZ)
+/// Environment variable: SCHEDULE_PATH
+pub struct Programming {
+    path: PathBuf,
+    shows: RwLock<Vec<Show>>,
+    active: RwLock<Option<ActiveShow>>,
+    /// Show songs still playing when their show ended, removed once they finish
+    ended_show_songs: Mutex<HashSet<SongId>>,
+    save_lock: Mutex<()>,
+}
+
+impl Programming {
+    pub fn from_env() -> Self {
+        Self {
+            path: PathBuf::from(std::env::var("SCHEDULE_PATH").unwrap_or_else(|_| DEFAULT_SCHEDULE_PATH.to_string())),
+            shows: RwLock::new(Vec::new()),
+            active: RwLock::new(None),
+            ended_show_songs: Mutex::new(HashSet::new()),
+            save_lock: Mutex::new(()),
+        }
+    }
+    
+    async fn load(&self) {
+        let shows: Vec<Show> = match tokio::fs::read(&self.path).await {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(shows) => shows,
+                Err(e) => {
+                    error!("Failed to parse schedule {:?}: {}", self.path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Failed to read schedule {:?}: {}", self.path, e);
+                return;
+            }
+        };
+        
+        info!("Loaded {} scheduled shows", shows.len());
+        *self.shows.write().await = shows;
+    }
+    
+    async fn save(&self) -> std::io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let data = serde_json::to_vec_pretty(&*self.shows.read().await).map_err(std::io::Error::other)?;
+        
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+    
+    pub async fn shows(&self) -> Vec<Show> {
+        self.shows.read().await.clone()
+    }
+    
+    pub async fn active(&self) -> Option<ActiveShow> {
+        self.active.read().await.clone()
+    }
+    
+    /// Queue entries added by the show on air, which play before pending requests
+    pub async fn show_song_ids(&self) -> HashSet<SongId> {
+        self.active.read().await.as_ref().map(|a| a.song_ids.clone()).unwrap_or_default()
+    }
+    
+    /// Whether a song that just finished was left playing by a show that has ended;
+    /// such songs are removed rather than rotated
+    pub async fn take_ended_show_song(&self, song_id: SongId) -> bool {
+        self.ended_show_songs.lock().await.remove(&song_id)
+    }
+    
+    /// Enabled shows may not overlap, so at most one is ever on air
+    /// Callers hold the write guard of `shows` from this check until the change is stored
+    fn check_overlap(shows: &[Show], input: &ShowInput, except_id: Option<&str>) -> Result<(), ShowError> {
+        if !input.enabled {
+            return Ok(());
+        }
+        match shows.iter().find(|s| Some(s.id.as_str()) != except_id && s.input.enabled && s.input.overlaps(input)) {
+            Some(other) => Err(ShowError::Overlap(other.input.name.clone())),
+            None => Ok(()),
+        }
+    }
+    
+    pub async fn create(&self, input: ShowInput) -> Result<Show, ShowError> {
+        input.validate().map_err(ShowError::Invalid)?;
+        
+        let show = {
+            let mut shows = self.shows.write().await;
+            Self::check_overlap(&shows, &input, None)?;
+            let show = Show {
+                id: Uuid::new_v4().to_string(),
+                input,
+            };
+            shows.push(show.clone());
+            show
+        };
+        self.save().await.map_err(ShowError::Io)?;
+        Ok(show)
+    }
+    
+    /// Returns None when there is no show with that id
+    pub async fn update(&self, id: &str, input: ShowInput) -> Result<Option<Show>, ShowError> {
+        input.validate().map_err(ShowError::Invalid)?;
+        
+        let show = {
+            let mut shows = self.shows.write().await;
+            Self::check_overlap(&shows, &input, Some(id))?;
+            let Some(show) = shows.iter_mut().find(|s| s.id == id) else {
+                return Ok(None);
+            };
+            show.input = input;
+            show.clone()
+        };
+        self.save().await.map_err(ShowError::Io)?;
+        Ok(Some(show))
+    }
+    
+    pub async fn delete(&self, id: &str) -> Result<bool, ShowError> {
+        let removed = {
+            let mut shows = self.shows.write().await;
+            let before = shows.len();
+            shows.retain(|s| s.id != id);
+            shows.len() != before
+        };
+        if removed {
+            self.save().await.map_err(ShowError::Io)?;
+        }
+        Ok(removed)
+    }
+    
+    /// The enabled show whose slot contains `now`, with its remaining minutes
+    async fn scheduled_at(&self, now: DateTime<Local>) -> Option<(Show, i64)> {
+        let minute = minute_of_week(now);
+        self.shows.read().await.iter()
+            .filter(|s| s.input.enabled)
+            .find_map(|s| s.input.remaining_at(minute).map(|remaining| (s.clone(), remaining)))
+    }
+}
+
+/// Filenames a show plays, enough to fill `minutes`
+async fn show_tracks(state: &AppState, source: &ShowSource, minutes: i64) -> Result<Vec<String>, String> {
+    let mut tracks: Vec<(String, Option<f64>)> = match source {
+        ShowSource::Playlist { name } => {
+            let songs = state.mpd_client.lock().await
+                .command(commands::GetPlaylist(name))
+                .await
+                .map_err(|e| format!("Failed to read playlist {}: {}", name, e))?;
+            songs.into_iter()
+                .map(|song| (song.url, song.duration.map(|d| d.as_secs_f64())))
+                .collect()
+        }
+        ShowSource::Filter { genre, artist, album } => {
+            let filter = LibraryFilter {
+                uploader: None,
+                artist: artist.clone(),
+                album: album.clone(),
+                genre: genre.clone(),
+            };
+            let mut tracks = library_tracks(state, &filter, SortKey::AddedAt, false).await;
+            tracks.shuffle(&mut rand::thread_rng());
+            tracks.into_iter().map(|t| (t.track.filename, t.track.duration)).collect()
+        }
+        ShowSource::Host { username } => {
+            let filter = LibraryFilter {
+                uploader: Some(username.clone()),
+                ..Default::default()
+            };
+            let mut tracks = library_tracks(state, &filter, SortKey::AddedAt, false).await;
+            tracks.shuffle(&mut rand::thread_rng());
+            tracks.into_iter().map(|t| (t.track.filename, t.track.duration)).collect()
+        }
+    };
+    
+    let mut filled = 0.0;
+    let needed = minutes as f64 * 60.0;
+    tracks.truncate(MAX_SHOW_TRACKS);
+    Ok(tracks.into_iter()
+        .take_while(|(_, duration)| {
+            let fits = filled < needed;
+            filled += duration.unwrap_or(FALLBACK_TRACK_SECS);
+            fits
+        })
+        .map(|(filename, _)| filename)
+        .collect())
+}
+
+/// Queue a show's tracks right after the current song, ahead of pending requests
+async fn start_show(state: &AppState, show: Show, remaining_minutes: i64) {
+    let filenames = match show_tracks(state, &show.input.source, remaining_minutes).await {
+        Ok(filenames) => filenames,
+        Err(e) => {
+            error!("Failed to pick tracks for show {}: {}", show.input.name, e);
+            Vec::new()
+        }
+    };
+    
+    let mut song_ids = HashSet::new();
+    {
+        let client = state.mpd_client.lock().await;
+        let first_slot = match client.command(commands::Status).await {
+            Ok(status) => status.current_song.map(|(pos, _)| pos.0 + 1).unwrap_or(0),
+            Err(e) => {
+                error!("Failed to get status: {}", e);
+                0
+            }
+        };
+        
+        for (offset, filename) in filenames.iter().enumerate() {
+            let song_id = match client.command(commands::Add::uri(filename).at(SongPosition(first_slot + offset))).await {
+                Ok(song_id) => song_id,
+                Err(e) => {
+                    warn!("Failed to queue {} for show {}: {}", filename, show.input.name, e);
+                    continue;
+                }
+            };
+            apply_cue_range(state, &client, song_id, filename).await;
+            state.queue_requesters.write().await.insert(song_id, show.input.name.clone());
+            song_ids.insert(song_id);
+        }
+        
+        let now = Utc::now();
+        *state.programming.active.write().await = Some(ActiveShow {
+            show: show.clone(),
+            started_at: now,
+            ends_at: now + Duration::minutes(remaining_minutes),
+            song_ids,
+        });
+        if let Err(e) = sync_queue_schedule(state, &client).await {
+            error!("Failed to sync queue schedule: {}", e);
+        }
+    }
+    
+    info!("Show {} started with {} tracks", show.input.name, filenames.len());
+    let active = state.programming.active().await;
+    let message = serde_json::json!({
+        "type": "show_started",
+        "data": active
+    });
+    state.broadcast_message(&message.to_string()).await;
+}
+
+/// Drop the show's tracks so the regular queue takes over again
+/// The song on air keeps playing and is removed by the monitor once it finishes
+async fn end_show(state: &AppState) {
+    let Some(active) = state.programming.active.write().await.take() else {
+        return;
+    };
+    
+    {
+        let client = state.mpd_client.lock().await;
+        let current_id = client.command(commands::Status).await.ok()
+            .and_then(|status| status.current_song.map(|(_, id)| id));
+        for song_id in &active.song_ids {
+            if Some(*song_id) == current_id {
+                state.programming.ended_show_songs.lock().await.insert(*song_id);
+                continue;
+            }
+            // Entries that already played may have been removed or rotated; either is fine
+            let _ = client.command(commands::Delete::id(*song_id)).await;
+        }
+        if let Err(e) = sync_queue_schedule(state, &client).await {
+            error!("Failed to sync queue schedule: {}", e);
+        }
+    }
+    
+    info!("Show {} ended", active.show.input.name);
+    let message = serde_json::json!({
+        "type": "show_ended",
+        "data": active
+    });
+    state.broadcast_message(&message.to_string()).await;
+    let queue_update = serde_json::json!({
+        "type": "queue_update",
+        "data": {}
+    });
+    state.broadcast_message(&queue_update.to_string()).await;
+}
+
+/// Load the schedule and start and end shows as their slots come and go
+pub async fn start_programming_scheduler(state: AppState) {
+    state.programming.load().await;
+    
+    tokio::spawn(async move {
+        loop {
+            let scheduled = state.programming.scheduled_at(Local::now()).await;
+            let active_id = state.programming.active.read().await.as_ref().map(|a| a.show.id.clone());
+            
+            match scheduled {
+                // The show on air was edited or disabled, or its slot is over
+                _ if active_id.is_some() && scheduled.as_ref().map(|(s, _)| &s.id) != active_id.as_ref() => {
+                    end_show(&state).await;
+                    continue;
+                }
+                Some((show, remaining)) if active_id.is_none() => start_show(&state, show, remaining).await,
+                _ => {}
+            }
+            
+            tokio::time::sleep(tokio::time::Duration::from_secs(SCHEDULE_CHECK_INTERVAL_SECS)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn show(days: &[Weekday], start: &str, duration_minutes: u32) -> ShowInput {
+        ShowInput {
+            name: "Show".to_string(),
+            days: days.to_vec(),
+            start: start.to_string(),
+            duration_minutes,
+            source: ShowSource::Host { username: "host".to_string() },
+            enabled: true,
+        }
+    }
+    
+    #[test]
+    fn back_to_back_shows_do_not_overlap() {
+        let morning = show(&[Weekday::Mon], "08:00", 60);
+        let next = show(&[Weekday::Mon], "09:00", 60);
+        assert!(!morning.overlaps(&next));
+        assert!(!next.overlaps(&morning));
+        assert!(morning.overlaps(&show(&[Weekday::Mon], "08:59", 60)));
+    }
+    
+    #[test]
+    fn shows_past_midnight_overlap_the_next_morning() {
+        let late = show(&[Weekday::Fri], "23:00", 120);
+        assert!(late.overlaps(&show(&[Weekday::Sat], "00:30", 30)));
+        assert!(show(&[Weekday::Sat], "00:30", 30).overlaps(&late));
+        assert!(!late.overlaps(&show(&[Weekday::Sat], "01:00", 30)));
+        assert!(!late.overlaps(&show(&[Weekday::Fri], "00:30", 30)));
+    }
+    
+    #[test]
+    fn sunday_night_wraps_into_monday() {
+        let late = show(&[Weekday::Sun], "23:30", 60);
+        assert!(late.overlaps(&show(&[Weekday::Mon], "00:00", 10)));
+        assert!(show(&[Weekday::Mon], "00:00", 10).overlaps(&late));
+        assert!(!late.overlaps(&show(&[Weekday::Mon], "00:30", 10)));
+    }
+    
+    #[test]
+    fn any_shared_occurrence_is_an_overlap() {
+        let weekdays = show(&[Weekday::Mon, Weekday::Wed, Weekday::Fri], "18:00", 60);
+        assert!(weekdays.overlaps(&show(&[Weekday::Tue, Weekday::Wed], "18:30", 60)));
+        assert!(!weekdays.overlaps(&show(&[Weekday::Tue, Weekday::Thu], "18:30", 60)));
+    }
+    
+    #[test]
+    fn remaining_time_counts_down_to_the_end() {
+        let evening = show(&[Weekday::Tue], "20:00", 90);
+        let start = 24 * 60 + 20 * 60;
+        assert_eq!(evening.remaining_at(start), Some(90));
+        assert_eq!(evening.remaining_at(start + 89), Some(1));
+        assert_eq!(evening.remaining_at(start + 90), None);
+        assert_eq!(evening.remaining_at(start - 1), None);
+    }
+    
+    #[test]
+    fn remaining_time_follows_a_show_across_midnight_and_the_week_end() {
+        let late = show(&[Weekday::Sun], "23:00", 120);
+        assert_eq!(late.remaining_at(MINUTES_PER_WEEK - 30), Some(90));
+        // Monday 00:30
+        assert_eq!(late.remaining_at(30), Some(30));
+        assert_eq!(late.remaining_at(60), None);
+    }
+    
+    #[test]
+    fn minute_of_week_starts_on_monday() {
+        use chrono::TimeZone;
+        // 2026-10-19 is a Monday
+        let monday = Local.with_ymd_and_hms(2026, 10, 19, 0, 30, 0).unwrap();
+        assert_eq!(minute_of_week(monday), 30);
+        let sunday = Local.with_ymd_and_hms(2026, 10, 25, 23, 59, 0).unwrap();
+        assert_eq!(minute_of_week(sunday), MINUTES_PER_WEEK - 1);
+    }
+    
+    #[test]
+    fn validation_rejects_bad_times_and_durations() {
+        assert!(show(&[Weekday::Mon], "08:00", 60).validate().is_ok());
+        assert!(show(&[Weekday::Mon], "24:00", 60).validate().is_err());
+        assert!(show(&[Weekday::Mon], "8am", 60).validate().is_err());
+        assert!(show(&[Weekday::Mon], "08:00", 0).validate().is_err());
+        assert!(show(&[Weekday::Mon], "08:00", 24 * 60 + 1).validate().is_err());
+        assert!(show(&[], "08:00", 60).validate().is_err());
+    }
+}
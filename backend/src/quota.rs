@@ -0,0 +1,276 @@
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+use crate::config::env_parse;
+use crate::models::{QuotaAllowance, QuotaStatus};
+use crate::mpd_manager::{queue_file, QueueError};
+use crate::state::{AppState, PendingRequest};
+use crate::storage::parse_size;
+
+const DEFAULT_MAX_QUEUED_TRACKS: u64 = 10;
+const DEFAULT_MAX_USER_STORAGE: u64 = 100 * 1024 * 1024; // 100 MB per user
+const DEFAULT_MAX_UPLOADS_PER_HOUR: u64 = 20;
+const DEFAULT_REQUEST_COOLDOWN_MINUTES: u64 = 30;
+
+/// Per-user upload and request limits
+/// Environment variables: USER_MAX_QUEUED_TRACKS, USER_MAX_STORAGE (bytes or "50MB"), USER_MAX_UPLOADS_PER_HOUR,
+/// REQUEST_COOLDOWN_MINUTES
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    pub max_queued_tracks: u64,
+    pub max_bytes: u64,
+    pub max_uploads_per_hour: u64,
+    /// How long after it last played a track can be requested again
+    pub request_cooldown: Duration,
+}
+
+impl QuotaConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_queued_tracks: env_parse("USER_MAX_QUEUED_TRACKS", DEFAULT_MAX_QUEUED_TRACKS),
+            max_bytes: match std::env::var("USER_MAX_STORAGE") {
+                Ok(val) => parse_size(&val).unwrap_or_else(|| {
+                    warn!("Invalid USER_MAX_STORAGE format: '{}', using default", val);
+                    DEFAULT_MAX_USER_STORAGE
+                }),
+                Err(_) => DEFAULT_MAX_USER_STORAGE,
+            },
+            max_uploads_per_hour: env_parse("USER_MAX_UPLOADS_PER_HOUR", DEFAULT_MAX_UPLOADS_PER_HOUR),
+            request_cooldown: Duration::minutes(
+                env_parse("REQUEST_COOLDOWN_MINUTES", DEFAULT_REQUEST_COOLDOWN_MINUTES) as i64,
+            ),
+        }
+    }
+}
+
+/// Which per-user limit an upload ran into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaExceeded {
+    QueuedTracks,
+    UploadsPerHour,
+    Storage,
+}
+
+impl QuotaExceeded {
+    pub fn message(&self) -> &'static str {
+        match self {
+            QuotaExceeded::QueuedTracks => "You already have the maximum number of tracks waiting in the queue",
+            QuotaExceeded::UploadsPerHour => "Hourly upload limit reached",
+            QuotaExceeded::Storage => "Your personal storage quota is full",
+        }
+    }
+}
+
+/// Keeps the per-user upload history needed for the hourly rate limit
+pub struct QuotaTracker {
+    pub config: QuotaConfig,
+    uploads: RwLock<HashMap<String, Vec<DateTime<Utc>>>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            config,
+            uploads: RwLock::new(HashMap::new()),
+        }
+    }
+    
+    /// Record a successful upload for the hourly limit
+    pub async fn record_upload(&self, username: &str) {
+        let mut uploads = self.uploads.write().await;
+        let history = uploads.entry(username.to_string()).or_default();
+        let cutoff = Utc::now() - Duration::hours(1);
+        history.retain(|t| *t > cutoff);
+        history.push(Utc::now());
+    }
+    
+    /// Uploads within the last hour, oldest first
+    async fn recent_uploads(&self, username: &str) -> Vec<DateTime<Utc>> {
+        let uploads = self.uploads.read().await;
+        let cutoff = Utc::now() - Duration::hours(1);
+        uploads.get(username)
+            .map(|history| history.iter().filter(|t| **t > cutoff).copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+fn allowance(used: u64, limit: u64) -> QuotaAllowance {
+    QuotaAllowance {
+        used,
+        limit,
+        remaining: limit.saturating_sub(used),
+    }
+}
+
+/// Current usage and remaining allowance for a user
+pub async fn get_quota_status(state: &AppState, username: &str) -> QuotaStatus {
+    let config = &state.quota.config;
+    
+    let queued = {
+        let pending = state.pending_requests.lock().await;
+        pending.iter().filter(|r| r.added_by == username).count() as u64
+    };
+    let stored = state.storage.user_usage(&sanitize_filename::sanitize(username)).await;
+    let recent = state.quota.recent_uploads(username).await;
+    
+    QuotaStatus {
+        username: username.to_string(),
+        queued_tracks: allowance(queued, config.max_queued_tracks),
+        storage_bytes: allowance(stored, config.max_bytes),
+        uploads_this_hour: allowance(recent.len() as u64, config.max_uploads_per_hour),
+        next_upload_slot_at: if recent.len() as u64 >= config.max_uploads_per_hour {
+            recent.first().map(|t| *t + Duration::hours(1))
+        } else {
+            None
+        },
+    }
+}
+
+/// Check whether a user may start another upload
+pub fn check_upload_allowed(status: &QuotaStatus) -> Result<(), QuotaExceeded> {
+    if status.queued_tracks.remaining == 0 {
+        return Err(QuotaExceeded::QueuedTracks);
+    }
+    if status.uploads_this_hour.remaining == 0 {
+        return Err(QuotaExceeded::UploadsPerHour);
+    }
+    if status.storage_bytes.remaining == 0 {
+        return Err(QuotaExceeded::Storage);
+    }
+    Ok(())
+}
+
+/// Why a request to queue a library track was turned down
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestRejected {
+    /// The track is already waiting in the queue
+    AlreadyQueued,
+    /// The track played recently and can be requested again at the given time
+    Cooldown(DateTime<Utc>),
+    /// The user already has the maximum number of tracks waiting
+    TooManyPending,
+}
+
+impl RequestRejected {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RequestRejected::AlreadyQueued => "already_queued",
+            RequestRejected::Cooldown(_) => "cooldown",
+            RequestRejected::TooManyPending => "too_many_pending",
+        }
+    }
+    
+    pub fn message(&self) -> String {
+        match self {
+            RequestRejected::AlreadyQueued => "This track is already in the queue".to_string(),
+            RequestRejected::Cooldown(available_at) => {
+                let minutes = (*available_at - Utc::now()).num_minutes().max(0) + 1;
+                format!("This track played recently and can be requested again in {} minutes", minutes)
+            }
+            RequestRejected::TooManyPending => QuotaExceeded::QueuedTracks.message().to_string(),
+        }
+    }
+}
+
+/// Check a request against the requests already waiting (duplicates, per-user limit)
+fn check_pending(
+    pending: &[PendingRequest],
+    config: &QuotaConfig,
+    username: &str,
+    filename: &str,
+) -> Result<(), RequestRejected> {
+    if pending.iter().any(|r| r.filename == filename) {
+        return Err(RequestRejected::AlreadyQueued);
+    }
+    let queued = pending.iter().filter(|r| r.added_by == username).count() as u64;
+    if queued >= config.max_queued_tracks {
+        return Err(RequestRejected::TooManyPending);
+    }
+    Ok(())
+}
+
+/// Check that a track didn't play too recently to be requested; based on the persisted
+/// play history, so cooldowns survive restarts
+async fn check_cooldown(state: &AppState, track_id: &str) -> Result<(), RequestRejected> {
+    if let Some(last_played) = state.history.last_played_at(track_id).await {
+        let available_at = last_played + state.quota.config.request_cooldown;
+        if available_at > Utc::now() {
+            return Err(RequestRejected::Cooldown(available_at));
+        }
+    }
+    Ok(())
+}
+
+/// Queue an existing track for a user, if the request rules allow it
+pub async fn request_track(
+    state: &AppState,
+    username: &str,
+    track_id: &str,
+    filename: &str,
+) -> Result<(), QueueError> {
+    check_cooldown(state, track_id).await.map_err(QueueError::Rejected)?;
+    let config = &state.quota.config;
+    queue_file(state, filename, username, |pending| check_pending(pending, config, username, filename)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_client::commands::SongId;
+    
+    fn config(max_queued_tracks: u64) -> QuotaConfig {
+        QuotaConfig {
+            max_queued_tracks,
+            max_bytes: 1024,
+            max_uploads_per_hour: 10,
+            request_cooldown: Duration::minutes(60),
+        }
+    }
+    
+    fn pending(user: &str, filename: &str) -> PendingRequest {
+        PendingRequest {
+            song_id: SongId(1),
+            filename: filename.to_string(),
+            added_by: user.to_string(),
+            requested_at: Utc::now(),
+        }
+    }
+    
+    fn allowance(remaining: u64) -> QuotaAllowance {
+        QuotaAllowance { used: 5 - remaining, limit: 5, remaining }
+    }
+    
+    fn status(queued: u64, storage: u64, uploads: u64) -> QuotaStatus {
+        QuotaStatus {
+            username: "alice".to_string(),
+            queued_tracks: allowance(queued),
+            storage_bytes: allowance(storage),
+            uploads_this_hour: allowance(uploads),
+            next_upload_slot_at: None,
+        }
+    }
+    
+    #[test]
+    fn a_track_waiting_in_the_queue_cannot_be_requested_again() {
+        let queue = [pending("bob", "a.mp3")];
+        assert_eq!(check_pending(&queue, &config(5), "alice", "a.mp3"), Err(RequestRejected::AlreadyQueued));
+        assert_eq!(check_pending(&queue, &config(5), "alice", "b.mp3"), Ok(()));
+    }
+    
+    #[test]
+    fn pending_requests_are_capped_per_user() {
+        let queue = [pending("alice", "a.mp3"), pending("alice", "b.mp3"), pending("bob", "c.mp3")];
+        assert_eq!(check_pending(&queue, &config(2), "alice", "d.mp3"), Err(RequestRejected::TooManyPending));
+        assert_eq!(check_pending(&queue, &config(2), "bob", "d.mp3"), Ok(()));
+    }
+    
+    #[test]
+    fn uploads_stop_when_any_allowance_runs_out() {
+        assert_eq!(check_upload_allowed(&status(1, 1, 1)), Ok(()));
+        assert_eq!(check_upload_allowed(&status(0, 1, 1)), Err(QuotaExceeded::QueuedTracks));
+        assert_eq!(check_upload_allowed(&status(1, 1, 0)), Err(QuotaExceeded::UploadsPerHour));
+        assert_eq!(check_upload_allowed(&status(1, 0, 1)), Err(QuotaExceeded::Storage));
+    }
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::jobs::{fail_job, start_job, update_job};
+use crate::models::ResumableUploadStatus;
+use crate::state::AppState;
+
+const DEFAULT_STAGING_DIR: &str = "data/partial-uploads";
+const DEFAULT_TTL_HOURS: i64 = 24;
+const DEFAULT_MAX_SESSIONS_PER_USER: usize = 3;
+const EXPIRY_CHECK_INTERVAL_SECS: u64 = 600;
+
+/// A resumable upload in progress, persisted next to its partial file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub username: String,
+    pub filename: String,
+    pub size: u64,
+    pub offset: u64,
+    /// SHA-256 announced by the client, checked on finalize
+    pub sha256: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A session in the store; the owner and declared size are kept outside the lock
+/// so they can be read while a chunk is being written
+#[derive(Clone)]
+pub struct SessionHandle {
+    pub owner: String,
+    pub size: u64,
+    pub session: Arc<Mutex<UploadSession>>,
+}
+
+impl SessionHandle {
+    fn new(session: UploadSession) -> Self {
+        Self {
+            owner: session.username.clone(),
+            size: session.size,
+            session: Arc::new(Mutex::new(session)),
+        }
+    }
+}
+
+/// Why a session couldn't be started
+#[derive(Debug)]
+pub enum CreateSessionError {
+    /// The user already has the maximum number of unfinished uploads
+    TooManySessions(usize),
+    Io(std::io::Error),
+}
+
+/// Staging area for resumable uploads
+///
+/// Partial files live outside `uploads/` so MPD and the storage index never see
+/// them. Each session has a `{id}.part` file and a `{id}.json` sidecar, so uploads
+/// can be resumed across backend restarts.
+pub struct ResumableUploads {
+    staging_dir: PathBuf,
+    ttl: Duration,
+    max_sessions_per_user: usize,
+    sessions: Mutex<HashMap<String, SessionHandle>>,
+}
+
+impl ResumableUploads {
+    /// Environment variables: UPLOAD_STAGING_DIR, RESUMABLE_UPLOAD_TTL_HOURS,
+    /// RESUMABLE_MAX_SESSIONS_PER_USER
+    pub fn from_env() -> Self {
+        let staging_dir = std::env::var("UPLOAD_STAGING_DIR")
+            .unwrap_or_else(|_| DEFAULT_STAGING_DIR.to_string());
+        let ttl_hours = std::env::var("RESUMABLE_UPLOAD_TTL_HOURS")
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(DEFAULT_TTL_HOURS);
+        let max_sessions_per_user = std::env::var("RESUMABLE_MAX_SESSIONS_PER_USER")
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(DEFAULT_MAX_SESSIONS_PER_USER);
+        
+        Self {
+            staging_dir: PathBuf::from(staging_dir),
+            ttl: Duration::hours(ttl_hours),
+            max_sessions_per_user,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    pub fn part_path(&self, id: &str) -> PathBuf {
+        self.staging_dir.join(format!("{}.part", id))
+    }
+    
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.staging_dir.join(format!("{}.json", id))
+    }
+    
+    pub fn status(&self, session: &UploadSession) -> ResumableUploadStatus {
+        ResumableUploadStatus {
+            upload_id: session.id.clone(),
+            filename: session.filename.clone(),
+            size: session.size,
+            offset: session.offset,
+            expires_at: session.updated_at + self.ttl,
+        }
+    }
+    
+    /// Restore sessions left over from a previous run
+    /// The offset is taken from the partial file, which is what actually reached the disk
+    pub async fn load(&self) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.staging_dir).await?;
+        
+        let mut sessions = self.sessions.lock().await;
+        let mut entries = tokio::fs::read_dir(&self.staging_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            
+            let mut session = match tokio::fs::read(&path).await
+                .ok()
+                .and_then(|data| serde_json::from_slice::<UploadSession>(&data).ok())
+            {
+                Some(session) => session,
+                None => {
+                    warn!("Ignoring unreadable upload session {:?}", path);
+                    continue;
+                }
+            };
+            
+            session.offset = tokio::fs::metadata(self.part_path(&session.id))
+                .await
+                .map(|m| m.len().min(session.size))
+                .unwrap_or(0);
+            sessions.insert(session.id.clone(), SessionHandle::new(session));
+        }
+        
+        if !sessions.is_empty() {
+            info!("Restored {} resumable upload sessions", sessions.len());
+        }
+        Ok(())
+    }
+    
+    /// Start a session, unless the user already has too many unfinished uploads
+    pub async fn create(
+        &self,
+        username: &str,
+        filename: &str,
+        size: u64,
+        sha256: Option<String>,
+    ) -> Result<UploadSession, CreateSessionError> {
+        // Held until the session is stored, so concurrent requests can't exceed the cap
+        let mut sessions = self.sessions.lock().await;
+        if sessions.values().filter(|h| h.owner == username).count() >= self.max_sessions_per_user {
+            return Err(CreateSessionError::TooManySessions(self.max_sessions_per_user));
+        }
+        
+        let now = Utc::now();
+        let session = UploadSession {
+            id: Uuid::new_v4().to_string(),
+            username: username.to_string(),
+            filename: filename.to_string(),
+            size,
+            offset: 0,
+            sha256: sha256.map(|h| h.to_lowercase()),
+            created_at: now,
+            updated_at: now,
+        };
+        
+        async {
+            tokio::fs::create_dir_all(&self.staging_dir).await?;
+            tokio::fs::File::create(self.part_path(&session.id)).await?;
+            self.save(&session).await
+        }.await.map_err(CreateSessionError::Io)?;
+        
+        sessions.insert(session.id.clone(), SessionHandle::new(session.clone()));
+        Ok(session)
+    }
+    
+    /// Declared size of a user's unfinished uploads, optionally leaving one out
+    pub async fn staged_bytes(&self, username: &str, except_id: Option<&str>) -> u64 {
+        self.sessions.lock().await
+            .iter()
+            .filter(|(id, h)| h.owner == username && Some(id.as_str()) != except_id)
+            .map(|(_, h)| h.size)
+            .sum()
+    }
+    
+    pub async fn get(&self, id: &str) -> Option<SessionHandle> {
+        self.sessions.lock().await.get(id).cloned()
+    }
+    
+    /// Persist a session's sidecar after its offset changed
+    pub async fn save(&self, session: &UploadSession) -> std::io::Result<()> {
+        let data = serde_json::to_vec(session).map_err(std::io::Error::other)?;
+        tokio::fs::write(self.meta_path(&session.id), data).await
+    }
+    
+    /// Forget a session and delete its staged files
+    pub async fn discard(&self, id: &str) {
+        self.sessions.lock().await.remove(id);
+        let _ = tokio::fs::remove_file(self.part_path(id)).await;
+        let _ = tokio::fs::remove_file(self.meta_path(id)).await;
+    }
+    
+    /// Snapshot of all sessions
+    pub async fn sessions(&self) -> Vec<UploadSession> {
+        let handles: Vec<SessionHandle> = self.sessions.lock().await.values().cloned().collect();
+        let mut sessions = Vec::with_capacity(handles.len());
+        for handle in handles {
+            sessions.push(handle.session.lock().await.clone());
+        }
+        sessions
+    }
+    
+    /// Discard sessions that haven't received data within the TTL, returning their ids
+    pub async fn expire(&self) -> Vec<String> {
+        let cutoff = Utc::now() - self.ttl;
+        let expired: Vec<String> = {
+            let sessions = self.sessions.lock().await;
+            let mut expired = Vec::new();
+            for (id, handle) in sessions.iter() {
+                // A session that is locked is receiving data right now
+                if let Ok(session) = handle.session.try_lock() {
+                    if session.updated_at < cutoff {
+                        expired.push(id.clone());
+                    }
+                }
+            }
+            expired
+        };
+        
+        for id in &expired {
+            info!("Expiring abandoned upload {}", id);
+            self.discard(id).await;
+        }
+        expired
+    }
+}
+
+/// Restore sessions from the staging directory and periodically drop abandoned ones
+pub async fn start_resumable_upload_expiry(state: AppState) {
+    if let Err(e) = state.resumable_uploads.load().await {
+        error!("Failed to restore resumable uploads: {}", e);
+    }
+    for session in state.resumable_uploads.sessions().await {
+        start_job(&state, Some(session.id.clone()), &session.username, &session.filename, Some(session.size)).await;
+        update_job(&state, &session.id, |job| job.bytes_received = session.offset).await;
+    }
+    
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(EXPIRY_CHECK_INTERVAL_SECS)).await;
+            let expired = state.resumable_uploads.expire().await;
+            for id in &expired {
+                fail_job(&state, id, "Upload expired").await;
+            }
+            if !expired.is_empty() {
+                info!("Expired {} abandoned uploads", expired.len());
+            }
+        }
+    });
+}
@@ -0,0 +1,168 @@
+use log::warn;
+use mpd_client::commands::SongId;
+use mpd_client::responses::SongInQueue;
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+
+use crate::jingles::is_jingle;
+use crate::mpd_manager::track_id_from_filename;
+use crate::state::AppState;
+
+const DEFAULT_ARTIST_WINDOW: usize = 3;
+
+/// Where finished songs go in the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationMode {
+    /// Always to the end, so the queue cycles in a fixed order
+    Sequential,
+    /// To a random spot in the back half of the upcoming tracks
+    Shuffle,
+}
+
+/// Environment variables: ROTATION_MODE (sequential, shuffle), ROTATION_ARTIST_WINDOW
+#[derive(Debug, Clone)]
+pub struct RotationConfig {
+    pub mode: RotationMode,
+    /// Shuffle only: tracks on either side of a reinserted song that shouldn't share its artist
+    pub artist_window: usize,
+}
+
+impl RotationConfig {
+    pub fn from_env() -> Self {
+        let mode = match std::env::var("ROTATION_MODE").map(|v| v.trim().to_lowercase()) {
+            Ok(v) if v == "shuffle" => RotationMode::Shuffle,
+            Ok(v) if v != "sequential" => {
+                warn!("Invalid ROTATION_MODE '{}', using sequential", v);
+                RotationMode::Sequential
+            }
+            _ => RotationMode::Sequential,
+        };
+        let artist_window = match std::env::var("ROTATION_ARTIST_WINDOW") {
+            Ok(val) => val.trim().parse().unwrap_or_else(|_| {
+                warn!("Invalid ROTATION_ARTIST_WINDOW value: '{}', using default", val);
+                DEFAULT_ARTIST_WINDOW
+            }),
+            Err(_) => DEFAULT_ARTIST_WINDOW,
+        };
+        Self { mode, artist_window }
+    }
+}
+
+/// Lowercased artist of a queue entry, from the catalog or else the file's tags
+async fn artist_of(state: &AppState, song: &SongInQueue) -> Option<String> {
+    let catalog_artist = state.tracks_metadata.read().await
+        .get(&track_id_from_filename(&song.song.url))
+        .and_then(|track| track.artist.clone());
+    catalog_artist
+        .or_else(|| song.song.artists().first().cloned())
+        .map(|artist| artist.trim().to_lowercase())
+}
+
+/// Final queue position for the song at `from`, which just finished playing
+///
+/// In shuffle mode, requests, show tracks and jingles right after the current song keep
+/// their place. The song goes into the back half of the remaining tracks, at a spot with
+/// no track by the same artist within `artist_window` on either side, or to the end of
+/// the queue when there is no such spot.
+pub async fn rotation_target(
+    state: &AppState,
+    config: &RotationConfig,
+    queue: &[SongInQueue],
+    from: usize,
+    current_id: Option<SongId>,
+) -> usize {
+    let last = queue.len() - 1;
+    if config.mode == RotationMode::Sequential {
+        return last;
+    }
+    
+    let played = &queue[from];
+    let order: Vec<&SongInQueue> = queue.iter().enumerate()
+        .filter(|(i, _)| *i != from)
+        .map(|(_, song)| song)
+        .collect();
+    
+    let priority: HashSet<SongId> = {
+        let pending = state.pending_requests.lock().await;
+        let mut ids = state.programming.show_song_ids().await;
+        ids.extend(pending.iter().map(|r| r.song_id));
+        ids.extend(order.iter().filter(|s| is_jingle(&s.song.url)).map(|s| s.id));
+        ids
+    };
+    let mut first_free = current_id
+        .and_then(|current| order.iter().position(|s| s.id == current))
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+    while order.get(first_free).is_some_and(|s| priority.contains(&s.id)) {
+        first_free += 1;
+    }
+    let back_start = first_free + (order.len() - first_free) / 2;
+    
+    let artist = artist_of(state, played).await;
+    let mut artists = Vec::with_capacity(order.len());
+    for song in &order {
+        artists.push(artist_of(state, song).await);
+    }
+    
+    let spots = artist_free_spots(&artists, &artist, back_start, config.artist_window);
+    spots.choose(&mut rand::thread_rng()).copied().unwrap_or(last)
+}
+
+/// Insertion points from `back_start` to the end with no track by `artist` within
+/// `window` on either side, or just the end when every spot has one
+///
+/// Inserting at `p` puts the song between `artists[p - 1]` and `artists[p]`.
+fn artist_free_spots(artists: &[Option<String>], artist: &Option<String>, back_start: usize, window: usize) -> Vec<usize> {
+    let conflicts = |p: usize| {
+        let near = p.saturating_sub(window)..(p + window).min(artists.len());
+        artists[near].iter().any(|a| artist.is_some() && a == artist)
+    };
+    let spots: Vec<usize> = (back_start..=artists.len()).filter(|&p| !conflicts(p)).collect();
+    if spots.is_empty() {
+        vec![artists.len()]
+    } else {
+        spots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn artists(names: &[&str]) -> Vec<Option<String>> {
+        names.iter().map(|name| (!name.is_empty()).then(|| name.to_string())).collect()
+    }
+    
+    #[test]
+    fn songs_only_go_where_no_track_by_their_artist_is_nearby() {
+        let queue = artists(&["a", "b", "c", "a", "d", "e"]);
+        let artist = Some("a".to_string());
+        assert_eq!(artist_free_spots(&queue, &artist, 0, 1), vec![2, 5, 6]);
+        assert_eq!(artist_free_spots(&queue, &artist, 3, 1), vec![5, 6]);
+    }
+    
+    #[test]
+    fn only_spots_from_the_back_half_are_picked() {
+        let queue = artists(&["a", "b", "c", "d"]);
+        assert_eq!(artist_free_spots(&queue, &Some("z".to_string()), 2, 3), vec![2, 3, 4]);
+    }
+    
+    #[test]
+    fn unknown_artists_never_conflict() {
+        let queue = artists(&["", "", ""]);
+        assert_eq!(artist_free_spots(&queue, &None, 1, 3), vec![1, 2, 3]);
+    }
+    
+    #[test]
+    fn songs_go_to_the_end_when_every_spot_has_their_artist_nearby() {
+        let queue = artists(&["a", "b", "a", "b", "a"]);
+        assert_eq!(artist_free_spots(&queue, &Some("a".to_string()), 0, 1), vec![5]);
+    }
+    
+    #[test]
+    fn a_crowded_queue_still_yields_a_spot() {
+        let queue = artists(&["a", "a", "a"]);
+        assert_eq!(artist_free_spots(&queue, &Some("a".to_string()), 2, 1), vec![3]);
+        assert_eq!(artist_free_spots(&[], &Some("a".to_string()), 0, 3), vec![0]);
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::state::PendingRequest;
+
+/// Compute a fair play order for the pending (not yet played) requests.
+///
+/// Requests are grouped by uploader and interleaved round-robin, so one person
+/// queueing 20 songs in a row only gets every Nth slot instead of the next 20.
+/// Within a round, uploaders who haven't been heard for the longest go first
+/// (never-played users before anyone else), ties broken by who asked first.
+/// Each uploader's own requests keep their submission order.
+pub fn fair_order(
+    pending: &[PendingRequest],
+    last_played: &HashMap<String, DateTime<Utc>>,
+) -> Vec<PendingRequest> {
+    // Group requests per uploader, preserving submission order within each group
+    let mut sorted: Vec<&PendingRequest> = pending.iter().collect();
+    sorted.sort_by_key(|r| r.requested_at);
+    
+    let mut groups: Vec<(String, Vec<&PendingRequest>)> = Vec::new();
+    for request in sorted {
+        match groups.iter_mut().find(|(user, _)| *user == request.added_by) {
+            Some((_, requests)) => requests.push(request),
+            None => groups.push((request.added_by.clone(), vec![request])),
+        }
+    }
+    
+    // Least recently played uploaders first; groups are already ordered by first request
+    groups.sort_by_key(|(user, _)| last_played.get(user).copied());
+    
+    let mut order = Vec::with_capacity(pending.len());
+    let mut round = 0;
+    while order.len() < pending.len() {
+        for (_, requests) in &groups {
+            if let Some(request) = requests.get(round) {
+                order.push((*request).clone());
+            }
+        }
+        round += 1;
+    }
+    
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use mpd_client::commands::SongId;
+    
+    fn request(id: u64, user: &str, minutes_ago: i64) -> PendingRequest {
+        PendingRequest {
+            song_id: SongId(id),
+            filename: format!("{}.mp3", id),
+            added_by: user.to_string(),
+            requested_at: Utc::now() - Duration::minutes(minutes_ago),
+        }
+    }
+    
+    fn ids(order: &[PendingRequest]) -> Vec<u64> {
+        order.iter().map(|r| r.song_id.0).collect()
+    }
+    
+    #[test]
+    fn users_take_turns() {
+        let pending = [
+            request(1, "alice", 10),
+            request(2, "alice", 9),
+            request(3, "alice", 8),
+            request(4, "bob", 7),
+            request(5, "carol", 6),
+            request(6, "bob", 5),
+        ];
+        assert_eq!(ids(&fair_order(&pending, &HashMap::new())), vec![1, 4, 5, 2, 6, 3]);
+    }
+    
+    #[test]
+    fn least_recently_heard_users_go_first() {
+        let pending = [request(1, "alice", 10), request(2, "bob", 9), request(3, "carol", 8)];
+        let last_played = HashMap::from([
+            ("alice".to_string(), Utc::now() - Duration::minutes(1)),
+            ("bob".to_string(), Utc::now() - Duration::minutes(30)),
+        ]);
+        // Carol never played, then Bob played longest ago
+        assert_eq!(ids(&fair_order(&pending, &last_played)), vec![3, 2, 1]);
+    }
+    
+    #[test]
+    fn each_users_requests_keep_submission_order() {
+        let pending = [request(2, "alice", 5), request(1, "alice", 10), request(3, "alice", 1)];
+        assert_eq!(ids(&fair_order(&pending, &HashMap::new())), vec![1, 2, 3]);
+        assert!(fair_order(&[], &HashMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+use log::{error, info, warn};
+use mpd_client::commands;
+use mpd_client::protocol::Command as RawCommand;
+use mpd_client::Client as MpdClient;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+const DEFAULT_SETTINGS_PATH: &str = "data/settings.json";
+const MAX_CROSSFADE_SECS: u32 = 30;
+
+/// Replay gain mode MPD applies using the tags written by loudness analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGain {
+    Off,
+    Track,
+    Album,
+    Auto,
+}
+
+impl ReplayGain {
+    /// Default mode until one is set through the API
+    /// Environment variable: REPLAYGAIN_MODE (off, track, album, auto)
+    fn from_env() -> Self {
+        match std::env::var("REPLAYGAIN_MODE").map(|v| v.trim().to_lowercase()) {
+            Ok(v) if v == "off" => ReplayGain::Off,
+            Ok(v) if v == "album" => ReplayGain::Album,
+            Ok(v) if v == "auto" => ReplayGain::Auto,
+            Ok(v) if v != "track" => {
+                warn!("Invalid REPLAYGAIN_MODE '{}', using track", v);
+                ReplayGain::Track
+            }
+            _ => ReplayGain::Track,
+        }
+    }
+    
+    fn mpd_mode(self) -> commands::ReplayGainMode {
+        match self {
+            ReplayGain::Off => commands::ReplayGainMode::Off,
+            ReplayGain::Track => commands::ReplayGainMode::Track,
+            ReplayGain::Album => commands::ReplayGainMode::Album,
+            ReplayGain::Auto => commands::ReplayGainMode::Auto,
+        }
+    }
+}
+
+/// MPD playback options the backend owns and reapplies on every connect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackSettings {
+    /// Seconds of overlap between tracks; 0 is a hard cut
+    pub crossfade_secs: u32,
+    /// Volume (dB) at which MixRamp overlaps tracks
+    pub mixramp_db: f64,
+    /// Seconds added to the MixRamp overlap; MixRamp is off when unset
+    pub mixramp_delay_secs: Option<f64>,
+    pub replay_gain_mode: ReplayGain,
+    pub random: bool,
+    pub repeat: bool,
+    pub consume: bool,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self {
+            crossfade_secs: 0,
+            mixramp_db: 0.0,
+            mixramp_delay_secs: None,
+            replay_gain_mode: ReplayGain::from_env(),
+            random: false,
+            repeat: false,
+            consume: false,
+        }
+    }
+}
+
+/// Distinguish an explicit `null` from a missing field
+fn explicit<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Changes to the playback settings; missing fields are left as they are
+#[derive(Debug, Default, Deserialize)]
+pub struct PlaybackSettingsUpdate {
+    pub crossfade_secs: Option<u32>,
+    pub mixramp_db: Option<f64>,
+    /// `null` turns MixRamp off
+    #[serde(default, deserialize_with = "explicit")]
+    pub mixramp_delay_secs: Option<Option<f64>>,
+    pub replay_gain_mode: Option<ReplayGain>,
+    pub random: Option<bool>,
+    pub repeat: Option<bool>,
+    pub consume: Option<bool>,
+}
+
+impl PlaybackSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.crossfade_secs > MAX_CROSSFADE_SECS {
+            return Err(format!("Crossfade must be between 0 and {} seconds", MAX_CROSSFADE_SECS));
+        }
+        if !self.mixramp_db.is_finite() || self.mixramp_db > 0.0 {
+            return Err("MixRamp threshold must be a dB value of 0 or less".to_string());
+        }
+        if self.mixramp_delay_secs.is_some_and(|d| !d.is_finite() || d < 0.0) {
+            return Err("MixRamp delay must be 0 seconds or more".to_string());
+        }
+        Ok(())
+    }
+    
+    fn merge(&self, update: PlaybackSettingsUpdate) -> Self {
+        Self {
+            crossfade_secs: update.crossfade_secs.unwrap_or(self.crossfade_secs),
+            mixramp_db: update.mixramp_db.unwrap_or(self.mixramp_db),
+            mixramp_delay_secs: update.mixramp_delay_secs.unwrap_or(self.mixramp_delay_secs),
+            replay_gain_mode: update.replay_gain_mode.unwrap_or(self.replay_gain_mode),
+            random: update.random.unwrap_or(self.random),
+            repeat: update.repeat.unwrap_or(self.repeat),
+            consume: update.consume.unwrap_or(self.consume),
+        }
+    }
+    
+    /// Send every option to MPD
+    pub async fn apply(&self, client: &MpdClient) -> Result<(), String> {
+        let raw = |name: &str, value: String| RawCommand::new(name).argument(value);
+        // MPD turns MixRamp off when the delay isn't a number
+        let delay = self.mixramp_delay_secs.map(|d| d.to_string()).unwrap_or_else(|| "nan".to_string());
+        
+        for command in [
+            raw("crossfade", self.crossfade_secs.to_string()),
+            raw("mixrampdb", self.mixramp_db.to_string()),
+            raw("mixrampdelay", delay),
+        ] {
+            client.raw_command(command).await.map_err(|e| format!("Failed to set playback option: {}", e))?;
+        }
+        client
+            .command(commands::SetReplayGainMode(self.replay_gain_mode.mpd_mode()))
+            .await
+            .map_err(|e| format!("Failed to set replay gain mode: {}", e))?;
+        client.command(commands::SetRandom(self.random)).await.map_err(|e| format!("Failed to set random: {}", e))?;
+        client.command(commands::SetRepeat(self.repeat)).await.map_err(|e| format!("Failed to set repeat: {}", e))?;
+        client.command(commands::SetConsume(self.consume)).await.map_err(|e| format!("Failed to set consume: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Why a settings update was refused
+#[derive(Debug)]
+pub enum SettingsError {
+    /// The merged settings are out of range
+    Invalid(String),
+    /// The settings file couldn't be written
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Invalid(message) => write!(f, "{}", message),
+            SettingsError::Io(e) => write!(f, "Failed to save settings: {}", e),
+        }
+    }
+}
+
+/// Admin-controlled playback settings, persisted as JSON
+/// Environment variable: SETTINGS_PATH
+pub struct SettingsStore {
+    path: PathBuf,
+    /// Updates hold the write guard until they are saved, so they can't interleave
+    playback: RwLock<PlaybackSettings>,
+}
+
+impl SettingsStore {
+    pub fn from_env() -> Self {
+        Self {
+            path: PathBuf::from(std::env::var("SETTINGS_PATH").unwrap_or_else(|_| DEFAULT_SETTINGS_PATH.to_string())),
+            playback: RwLock::new(PlaybackSettings::default()),
+        }
+    }
+    
+    pub async fn load(&self) {
+        let settings: PlaybackSettings = match tokio::fs::read(&self.path).await {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    error!("Failed to parse settings {:?}: {}", self.path, e);
+                    return;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Failed to read settings {:?}: {}", self.path, e);
+                return;
+            }
+        };
+        
+        info!("Loaded playback settings from {:?}", self.path);
+        *self.playback.write().await = settings;
+    }
+    
+    async fn save(&self, settings: &PlaybackSettings) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(settings).map_err(std::io::Error::other)?;
+        
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+    
+    pub async fn playback(&self) -> PlaybackSettings {
+        self.playback.read().await.clone()
+    }
+    
+    /// Validate, persist and store changes; the caller applies them to MPD
+    /// Nothing changes when the settings can't be saved
+    pub async fn update(&self, update: PlaybackSettingsUpdate) -> Result<PlaybackSettings, SettingsError> {
+        let mut playback = self.playback.write().await;
+        let settings = playback.merge(update);
+        settings.validate().map_err(SettingsError::Invalid)?;
+        
+        self.save(&settings).await.map_err(SettingsError::Io)?;
+        *playback = settings.clone();
+        Ok(settings)
+    }
+}
@@ -1,14 +1,41 @@
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use tokio::sync::{Mutex, RwLock};
 use std::collections::HashMap;
-use crate::models::Track;
+use crate::analysis::AnalysisQueue;
+use crate::dedup::FingerprintIndex;
+use crate::direct_play::DirectPlaySync;
+use crate::history::PlayHistory;
+use crate::jobs::UploadJobs;
+use crate::library::SearchIndex;
+use crate::lyrics::LyricsFollower;
+use crate::models::{Track, TrackStats};
+use crate::programming::Programming;
+use crate::quota::{QuotaConfig, QuotaTracker};
+use crate::resumable::ResumableUploads;
+use crate::settings::SettingsStore;
+use crate::storage::{get_max_total_storage, StorageManager};
+use chrono::{DateTime, Utc};
+use mpd_client::commands::SongId;
 use mpd_client::Client as MpdClient;
 use uuid::Uuid;
 
 pub struct SessionWrapper {
     pub id: Uuid,
     pub session: actix_ws::Session,
+    /// Opted in to direct playback of original files
+    pub direct_play: bool,
+    /// Who connected, used to deliver events meant for one user
+    pub username: Option<String>,
+}
+
+/// A queued track that hasn't started playing yet, used by the fairness scheduler
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub song_id: SongId,
+    pub filename: String,
+    pub added_by: String,
+    pub requested_at: DateTime<Utc>,
 }
 
 /// Tracks connections per IP address for rate limiting
@@ -93,6 +120,25 @@ pub struct AppState {
     pub ws_sessions: Arc<Mutex<Vec<SessionWrapper>>>,
     pub http_client: reqwest::Client,
     pub stream_connections: Arc<IpConnectionTracker>,
+    pub pending_requests: Arc<Mutex<Vec<PendingRequest>>>,
+    /// Who asked for each queue entry, when it differs from or outlives the pending request
+    pub queue_requesters: Arc<RwLock<HashMap<SongId, String>>>,
+    /// Set while an admin has paused or stopped playback, so nothing restarts it automatically
+    pub playback_held: Arc<AtomicBool>,
+    pub last_played_by_user: Arc<RwLock<HashMap<String, DateTime<Utc>>>>,
+    pub quota: Arc<QuotaTracker>,
+    pub track_stats: Arc<RwLock<HashMap<String, TrackStats>>>,
+    pub storage: Arc<StorageManager>,
+    pub fingerprints: Arc<FingerprintIndex>,
+    pub resumable_uploads: Arc<ResumableUploads>,
+    pub upload_jobs: Arc<UploadJobs>,
+    pub analysis_queue: Arc<AnalysisQueue>,
+    pub search_index: Arc<SearchIndex>,
+    pub history: Arc<PlayHistory>,
+    pub programming: Arc<Programming>,
+    pub settings: Arc<SettingsStore>,
+    pub direct_play: Arc<DirectPlaySync>,
+    pub lyrics: Arc<LyricsFollower>,
 }
 
 impl AppState {
@@ -115,15 +161,54 @@ impl AppState {
             ws_sessions: Arc::new(Mutex::new(Vec::new())),
             http_client,
             stream_connections: Arc::new(IpConnectionTracker::new(Self::MAX_STREAMS_PER_IP)),
+            pending_requests: Arc::new(Mutex::new(Vec::new())),
+            queue_requesters: Arc::new(RwLock::new(HashMap::new())),
+            playback_held: Arc::new(AtomicBool::new(false)),
+            last_played_by_user: Arc::new(RwLock::new(HashMap::new())),
+            quota: Arc::new(QuotaTracker::new(QuotaConfig::from_env())),
+            track_stats: Arc::new(RwLock::new(HashMap::new())),
+            storage: Arc::new(StorageManager::new("uploads", get_max_total_storage())),
+            fingerprints: Arc::new(FingerprintIndex::default()),
+            resumable_uploads: Arc::new(ResumableUploads::from_env()),
+            upload_jobs: Arc::new(UploadJobs::default()),
+            analysis_queue: Arc::new(AnalysisQueue::default()),
+            search_index: Arc::new(SearchIndex::default()),
+            history: Arc::new(PlayHistory::from_env()),
+            programming: Arc::new(Programming::from_env()),
+            settings: Arc::new(SettingsStore::from_env()),
+            direct_play: Arc::new(DirectPlaySync::default()),
+            lyrics: Arc::new(LyricsFollower::default()),
         }
     }
     
+    pub fn is_playback_held(&self) -> bool {
+        self.playback_held.load(Ordering::SeqCst)
+    }
+    
+    pub fn set_playback_held(&self, held: bool) {
+        self.playback_held.store(held, Ordering::SeqCst);
+    }
+    
     pub async fn broadcast_message(&self, message: &str) {
+        self.broadcast_to(message, |_| true).await;
+    }
+    
+    /// Send a message to the sessions that opted in to direct playback
+    pub async fn broadcast_direct_play(&self, message: &str) {
+        self.broadcast_to(message, |wrapper| wrapper.direct_play).await;
+    }
+    
+    /// Send a message to the sessions opened by `username`
+    pub async fn send_to_user(&self, username: &str, message: &str) {
+        self.broadcast_to(message, |wrapper| wrapper.username.as_deref() == Some(username)).await;
+    }
+    
+    async fn broadcast_to(&self, message: &str, filter: impl Fn(&SessionWrapper) -> bool) {
         let mut sessions = self.ws_sessions.lock().await;
         let mut to_remove = Vec::new();
         
         for (idx, wrapper) in sessions.iter_mut().enumerate() {
-            if wrapper.session.text(message.to_string()).await.is_err() {
+            if filter(wrapper) && wrapper.session.text(message.to_string()).await.is_err() {
                 to_remove.push(idx);
             }
         }
@@ -134,6 +219,13 @@ impl AppState {
         }
     }
     
+    pub async fn set_direct_play(&self, session_id: Uuid, enabled: bool) {
+        let mut sessions = self.ws_sessions.lock().await;
+        if let Some(wrapper) = sessions.iter_mut().find(|wrapper| wrapper.id == session_id) {
+            wrapper.direct_play = enabled;
+        }
+    }
+    
     pub async fn remove_session(&self, session_id: Uuid) {
         let mut sessions = self.ws_sessions.lock().await;
         sessions.retain(|wrapper| wrapper.id != session_id);
@@ -0,0 +1,73 @@
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+use crate::models::TrackStats;
+use crate::state::AppState;
+
+const DEFAULT_STATS_PATH: &str = "data/stats.json";
+
+/// Serializes stats writes so two saves never interleave
+static SAVE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Where play counts and likes are persisted
+/// Environment variable: STATS_PATH
+fn stats_path() -> PathBuf {
+    PathBuf::from(std::env::var("STATS_PATH").unwrap_or_else(|_| DEFAULT_STATS_PATH.to_string()))
+}
+
+/// Load persisted track stats, keeping only tracks whose file is still in storage
+pub async fn load_track_stats(state: &AppState) {
+    let path = stats_path();
+    let loaded: HashMap<String, TrackStats> = match tokio::fs::read(&path).await {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(stats) => stats,
+            Err(e) => {
+                error!("Failed to parse track stats {:?}: {}", path, e);
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Failed to read track stats {:?}: {}", path, e);
+            return;
+        }
+    };
+    
+    let total = loaded.len();
+    let mut stats = state.track_stats.write().await;
+    for (track_id, track_stats) in loaded {
+        if state.storage.find_by_track_id(&track_id).await.is_some() {
+            stats.insert(track_id, track_stats);
+        }
+    }
+    info!("Loaded stats for {} of {} tracks", stats.len(), total);
+}
+
+/// Write track stats to disk; the previous copy is replaced atomically
+pub async fn save_track_stats(state: &AppState) {
+    let _guard = SAVE_LOCK.lock().await;
+    
+    let data = match serde_json::to_vec(&*state.track_stats.read().await) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to serialize track stats: {}", e);
+            return;
+        }
+    };
+    
+    let path = stats_path();
+    let tmp_path = path.with_extension("json.tmp");
+    let result = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await
+    }.await;
+    
+    if let Err(e) = result {
+        error!("Failed to save track stats {:?}: {}", path, e);
+    }
+}
@@ -0,0 +1,354 @@
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+
+use crate::models::StorageInfo;
+use crate::mpd_manager::{extract_username_from_filename, track_id_from_filename};
+
+const DEFAULT_MAX_TOTAL_STORAGE: u64 = 300 * 1024 * 1024; // 300 MB default total storage limit
+const DEFAULT_RECONCILE_INTERVAL_SECS: u64 = 300;
+/// Suffixes of partial and temporary files that never belong in the index
+const TEMP_SUFFIXES: [&str; 3] = [".part", ".tmp", ".tagging"];
+
+/// Parse a byte size given as a plain number or with a suffix like "500MB", "1GB"
+pub fn parse_size(val: &str) -> Option<u64> {
+    let val = val.trim().to_uppercase();
+    if let Some(mb_pos) = val.find("MB") {
+        val[..mb_pos].trim().parse::<u64>().ok().map(|num| num * 1024 * 1024)
+    } else if let Some(gb_pos) = val.find("GB") {
+        val[..gb_pos].trim().parse::<u64>().ok().map(|num| num * 1024 * 1024 * 1024)
+    } else {
+        val.parse::<u64>().ok()
+    }
+}
+
+/// Get the maximum total storage size from environment variable or use default
+/// Environment variable: MAX_TOTAL_STORAGE (in bytes, or with suffix like "500MB", "1GB")
+pub fn get_max_total_storage() -> u64 {
+    match std::env::var("MAX_TOTAL_STORAGE") {
+        Ok(val) => parse_size(&val).unwrap_or_else(|| {
+            warn!("Invalid MAX_TOTAL_STORAGE format: '{}', using default", val);
+            DEFAULT_MAX_TOTAL_STORAGE
+        }),
+        Err(_) => DEFAULT_MAX_TOTAL_STORAGE,
+    }
+}
+
+/// A file in the uploads directory as seen by the storage index
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub filename: String,
+    pub track_id: String,
+    pub uploader: Option<String>,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    /// SHA-256 of the file contents, hex encoded
+    pub content_hash: String,
+}
+
+/// Hash a file's contents without blocking the executor
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Shared storage set aside for a batch upload that is still being received
+/// Whatever wasn't handed back with `release` is returned when it's dropped
+pub struct Reservation {
+    storage: Arc<StorageManager>,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// Hand back part of the reservation, e.g. once a received file is about to be stored
+    pub fn release(&mut self, bytes: u64) {
+        let bytes = bytes.min(self.bytes);
+        self.storage.reserved.fetch_sub(bytes, Ordering::SeqCst);
+        self.bytes -= bytes;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.storage.reserved.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// In-memory index of the uploads directory
+///
+/// Updated whenever the backend writes or deletes an upload, and reconciled with
+/// the directory periodically to pick up changes made behind its back.
+pub struct StorageManager {
+    root: PathBuf,
+    max_total: u64,
+    files: RwLock<HashMap<String, StoredFile>>,
+    /// Uploads still being received or ingested, left alone by reconciliation
+    writing: RwLock<HashSet<String>>,
+    /// Bytes held by outstanding reservations
+    reserved: AtomicU64,
+    last_reconciled_at: RwLock<Option<DateTime<Utc>>>,
+}
+
+impl StorageManager {
+    pub fn new(root: impl Into<PathBuf>, max_total: u64) -> Self {
+        Self {
+            root: root.into(),
+            max_total,
+            files: RwLock::new(HashMap::new()),
+            writing: RwLock::new(HashSet::new()),
+            reserved: AtomicU64::new(0),
+            last_reconciled_at: RwLock::new(None),
+        }
+    }
+    
+    pub fn max_total(&self) -> u64 {
+        self.max_total
+    }
+    
+    /// Full path of an upload on disk
+    pub fn path(&self, filename: &str) -> PathBuf {
+        self.root.join(filename)
+    }
+    
+    /// Read a file's metadata; the content hash is reused when given, computed otherwise
+    async fn stat(path: &Path, filename: String, content_hash: Option<String>) -> std::io::Result<Option<StoredFile>> {
+        let metadata = tokio::fs::metadata(path).await?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        
+        let content_hash = match content_hash {
+            Some(hash) => hash,
+            None => hash_file(path).await?,
+        };
+        
+        Ok(Some(StoredFile {
+            track_id: track_id_from_filename(&filename),
+            uploader: extract_username_from_filename(&filename),
+            size: metadata.len(),
+            modified: metadata.modified()?.into(),
+            content_hash,
+            filename,
+        }))
+    }
+    
+    /// Rebuild the index from the uploads directory
+    pub async fn reconcile(&self) -> std::io::Result<()> {
+        let mut scanned = HashMap::new();
+        let mut in_progress = Vec::new();
+        
+        if tokio::fs::try_exists(&self.root).await? {
+            let mut entries = tokio::fs::read_dir(&self.root).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let filename = entry.file_name().to_string_lossy().to_string();
+                
+                // Skip .gitkeep and other hidden files, and partial files
+                if filename.starts_with('.') || TEMP_SUFFIXES.iter().any(|suffix| filename.ends_with(suffix)) {
+                    continue;
+                }
+                // Uploads in progress are indexed by `record_write` once they are complete
+                if self.writing.read().await.contains(&filename) {
+                    in_progress.push(filename);
+                    continue;
+                }
+                
+                // A file can vanish between listing and stat; just skip it
+                // Only rehash files that changed since the last scan
+                let known_hash = match (self.files.read().await.get(&filename), entry.metadata().await) {
+                    (Some(known), Ok(metadata)) if metadata.len() == known.size
+                        && metadata.modified().ok().map(DateTime::<Utc>::from) == Some(known.modified) => {
+                        Some(known.content_hash.clone())
+                    }
+                    _ => None,
+                };
+                
+                match Self::stat(&entry.path(), filename.clone(), known_hash).await {
+                    Ok(Some(file)) => {
+                        scanned.insert(filename, file);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to stat {}: {}", filename, e),
+                }
+            }
+        }
+        
+        let mut files = self.files.write().await;
+        let previous = files.len();
+        // Keep those that were completed while the directory was being scanned
+        for filename in in_progress {
+            if let Some(file) = files.remove(&filename) {
+                scanned.insert(filename, file);
+            }
+        }
+        if previous != scanned.len() {
+            info!("Storage index reconciled: {} -> {} files", previous, scanned.len());
+        }
+        *files = scanned;
+        *self.last_reconciled_at.write().await = Some(Utc::now());
+        
+        Ok(())
+    }
+    
+    /// Mark a file that is about to be written; it stays out of the index until
+    /// `record_write` or `remove`
+    pub async fn start_write(&self, filename: &str) {
+        self.writing.write().await.insert(filename.to_string());
+    }
+    
+    /// Add or refresh a file after it was written, with its hash if it's already known
+    pub async fn record_write(&self, filename: &str, content_hash: Option<String>) -> std::io::Result<()> {
+        self.writing.write().await.remove(filename);
+        if let Some(file) = Self::stat(&self.path(filename), filename.to_string(), content_hash).await? {
+            self.files.write().await.insert(filename.to_string(), file);
+        }
+        Ok(())
+    }
+    
+    /// Delete a file from disk and from the index
+    pub async fn remove(&self, filename: &str) -> std::io::Result<()> {
+        let result = tokio::fs::remove_file(self.path(filename)).await;
+        // Drop it from the index even if it was already gone from disk
+        self.files.write().await.remove(filename);
+        self.writing.write().await.remove(filename);
+        match result {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Set `bytes` aside; callers check that they fit first
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Reservation {
+        self.reserved.fetch_add(bytes, Ordering::SeqCst);
+        Reservation {
+            storage: Arc::clone(self),
+            bytes,
+        }
+    }
+    
+    pub fn reserved(&self) -> u64 {
+        self.reserved.load(Ordering::SeqCst)
+    }
+    
+    pub async fn total_size(&self) -> u64 {
+        self.files.read().await.values().map(|f| f.size).sum()
+    }
+    
+    pub async fn files(&self) -> Vec<StoredFile> {
+        self.files.read().await.values().cloned().collect()
+    }
+    
+    pub async fn get(&self, filename: &str) -> Option<StoredFile> {
+        self.files.read().await.get(filename).cloned()
+    }
+    
+    pub async fn find_by_hash(&self, content_hash: &str) -> Option<StoredFile> {
+        self.files.read().await.values().find(|f| f.content_hash == content_hash).cloned()
+    }
+    
+    pub async fn find_by_track_id(&self, track_id: &str) -> Option<StoredFile> {
+        self.files.read().await.values().find(|f| f.track_id == track_id).cloned()
+    }
+    
+    /// Total size of the uploads of one (sanitized) username
+    pub async fn user_usage(&self, uploader: &str) -> u64 {
+        self.files.read().await.values()
+            .filter(|f| f.uploader.as_deref() == Some(uploader))
+            .map(|f| f.size)
+            .sum()
+    }
+    
+    pub async fn info(&self) -> StorageInfo {
+        let files = self.files.read().await;
+        let used_bytes: u64 = files.values().map(|f| f.size).sum();
+        let mut uploaders: Vec<&str> = files.values().filter_map(|f| f.uploader.as_deref()).collect();
+        uploaders.sort_unstable();
+        uploaders.dedup();
+        
+        StorageInfo {
+            used_bytes,
+            limit_bytes: self.max_total,
+            available_bytes: self.max_total.saturating_sub(used_bytes),
+            usage_percent: if self.max_total > 0 { used_bytes as f64 * 100.0 / self.max_total as f64 } else { 0.0 },
+            file_count: files.len(),
+            uploader_count: uploaders.len(),
+            oldest_upload_at: files.values().map(|f| f.modified).min(),
+            newest_upload_at: files.values().map(|f| f.modified).max(),
+            last_reconciled_at: *self.last_reconciled_at.read().await,
+        }
+    }
+}
+
+/// Periodically reconcile the storage index with the uploads directory
+/// Environment variable: STORAGE_RECONCILE_INTERVAL (seconds, 0 disables it)
+pub async fn start_storage_reconciler(storage: std::sync::Arc<StorageManager>) {
+    let interval_secs = std::env::var("STORAGE_RECONCILE_INTERVAL")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(DEFAULT_RECONCILE_INTERVAL_SECS);
+    if interval_secs == 0 {
+        info!("Periodic storage reconciliation disabled");
+        return;
+    }
+    
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(interval_secs)).await;
+            if let Err(e) = storage.reconcile().await {
+                error!("Failed to reconcile storage index: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn sizes_parse_with_and_without_suffixes() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("500MB"), Some(500 * 1024 * 1024));
+        assert_eq!(parse_size(" 2 gb "), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("0"), Some(0));
+    }
+    
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size("1.5GB"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("lots"), None);
+    }
+    
+    #[test]
+    fn reservations_are_returned_when_released_or_dropped() {
+        let storage = Arc::new(StorageManager::new("uploads", 1024));
+        let mut batch = storage.reserve(300);
+        let other = storage.reserve(100);
+        assert_eq!(storage.reserved(), 400);
+        
+        batch.release(120);
+        assert_eq!(storage.reserved(), 280);
+        batch.release(1000);
+        assert_eq!(storage.reserved(), 100);
+        drop(batch);
+        drop(other);
+        assert_eq!(storage.reserved(), 0);
+    }
+}